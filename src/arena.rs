@@ -1,4 +1,8 @@
-use bevy::{prelude::*, asset::Assets , utils::Instant};
+use bevy::{prelude::*, utils::Instant};
+use bevy::input::mouse::MouseMotion;
+
+use crate::controls::*;
+use crate::replay::ReplayRes;
 
 use super::GameState;
 pub struct ArenaPlugin;
@@ -6,9 +10,14 @@ pub struct ArenaPlugin;
 pub const ARENA_MAX_ANGLE: f32 = 3.14/6.0;
 pub const ARENA_ANG_MOMENTUM: f32 = 0.8;
 pub const ARENA_SIZE: f32 = 12.0;
+// Fastest the arena follows the stick, radians per second, so a flick does not jolt the ball
+pub const ARENA_STICK_SPEED: f32 = 3.0;
+// Radians of tilt per pixel of mouse drag, about a quarter of the window for the steepest angle
+pub const ARENA_MOUSE_SENSITIVITY: f32 = 0.0025;
 
 #[derive(Bundle, Default)]
 pub struct ArenaBundle {
+    pub arena: Arena,
     pub rotator: Rotator,
     pub return_anim: ReturnAnimation,
     #[bundle]
@@ -19,31 +28,32 @@ impl Plugin for ArenaPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_system_set(SystemSet::on_update(GameState::Running).with_system(system))
-            .add_system_set(SystemSet::on_update(GameState::RespawnShrink).with_system(return_to_neutral))
-            .init_resource::<ArenaRes>();
+            .add_system_set(SystemSet::on_update(GameState::RespawnGrow).with_system(return_to_neutral))
+            .init_resource::<TiltSettings>();
     }
 }
 
-pub struct ArenaRes {
-    pub mesh: Handle<Mesh>,
-    pub tex: Handle<Image>,
+pub fn arena_mesh(size: f32) -> Mesh {
+    Mesh::from(shape::Box {
+        max_x: size / 2.0,
+        max_y: 0.0,
+        max_z: size / 2.0,
+        min_x:-size / 2.0,
+        min_y:-0.5,
+        min_z:-size / 2.0,
+    })
 }
 
-impl FromWorld for ArenaRes {
-    fn from_world(world: &mut World) -> Self {
-        let arena_mesh_handle = world.resource_mut::<Assets<Mesh>>().add(
-            Mesh::from(shape::Box {
-                max_x: ARENA_SIZE / 2.0,
-                max_y: 0.0,
-                max_z: ARENA_SIZE / 2.0,
-                min_x:-ARENA_SIZE / 2.0,
-                min_y:-0.5,
-                min_z:-ARENA_SIZE / 2.0,
-            }));
+#[derive(Component)]
+pub struct Arena {
+    pub size: f32,
+    // Balls that have to be sunk in the final hole to finish the level
+    pub balls_to_win: usize,
+}
 
-        let tex_handle = world.resource::<AssetServer>().load("wood.png");
-        
-        ArenaRes { mesh: arena_mesh_handle, tex: tex_handle }
+impl Default for Arena {
+    fn default() -> Self {
+        Arena { size: ARENA_SIZE, balls_to_win: 1 }
     }
 }
 
@@ -52,6 +62,76 @@ pub struct Rotator {
     pub angle: Vec2,
 }
 
+impl Rotator {
+    // Tilt about x first, then about z, the physics relies on the same order
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_x(self.angle.x) * Quat::from_rotation_z(self.angle.y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TiltMode {
+    // Tilt actions and the gamepad stick
+    Standard,
+    // Dragging with the left mouse button grabs the board, the tilt actions
+    // still work while the button is up
+    MouseDrag,
+}
+
+impl TiltMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TiltMode::Standard => "Keys / gamepad",
+            TiltMode::MouseDrag => "Mouse drag",
+        }
+    }
+}
+
+// How the player input maps to the arena tilt, changed from the settings screen
+pub struct TiltSettings {
+    pub mode: TiltMode,
+    // Share of the stick travel around the center that is ignored
+    pub stick_deadzone: f32,
+    // Exponent applied to the stick travel past the deadzone, above 1.0
+    // small movements tilt less for finer control near level
+    pub stick_curve: f32,
+    // Radians per pixel dragged
+    pub mouse_sensitivity: f32,
+    // Dragging tips the board away from the mouse instead of towards it
+    pub mouse_invert: bool,
+}
+
+impl Default for TiltSettings {
+    fn default() -> Self {
+        TiltSettings {
+            mode: TiltMode::Standard,
+            stick_deadzone: 0.15,
+            stick_curve: 1.6,
+            mouse_sensitivity: ARENA_MOUSE_SENSITIVITY,
+            mouse_invert: false,
+        }
+    }
+}
+
+// Stick position with the deadzone cut out and the response curve applied,
+// still pointing the same way and at most 1.0 long
+pub fn stick_response(stick: Vec2, settings: &TiltSettings) -> Vec2 {
+    let travel = stick.length();
+    if travel <= settings.stick_deadzone {
+        return Vec2::ZERO;
+    }
+    let scaled = ((travel - settings.stick_deadzone) / (1.0 - settings.stick_deadzone)).min(1.0);
+    stick / travel * scaled.powf(settings.stick_curve)
+}
+
+fn left_stick(gamepads: &Gamepads, axes: &Axis<GamepadAxis>) -> Option<Vec2> {
+    let gamepad = *gamepads.iter().next()?;
+    Some(Vec2::new(
+        axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX)).unwrap_or(0.0),
+        axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY)).unwrap_or(0.0),
+    ))
+}
+
 #[derive(Component, Default)]
 pub struct ReturnAnimation {
     start_time: Option<Instant>,
@@ -59,31 +139,80 @@ pub struct ReturnAnimation {
 }
 
 
+// Tilt actions ramp the tilt up while they are held, the left stick sets
+// the tilt directly: full travel is the steepest angle. In the mouse drag
+// mode the board follows the mouse while the left button is down.
+#[allow(clippy::too_many_arguments)]
 fn system(
     _commands: Commands,
     time: Res<Time>,
-    keyboard: Res<Input<KeyCode>>,
+    actions: Res<ActionState>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<Input<MouseButton>>,
+    mut motion_events: EventReader<MouseMotion>,
+    settings: Res<TiltSettings>,
+    replay: Res<ReplayRes>,
     mut query: Query<(&mut Transform, &mut Rotator)>,
 ) {
-    
+    // A replay sets the tilt of every physics step, the board just shows it
+    if replay.is_playing() {
+        for (mut tran, comp) in query.iter_mut() {
+            tran.rotation = comp.rotation();
+        }
+        return;
+    }
+
+    let keys_held = [Action::TiltUp, Action::TiltDown, Action::TiltLeft, Action::TiltRight]
+        .iter()
+        .any(|action| actions.pressed(*action));
+    let stick = match settings.mode {
+        TiltMode::Standard if !keys_held => left_stick(&gamepads, &axes),
+        _ => None,
+    };
+    let dragged = motion_events.iter().fold(Vec2::ZERO, |sum, event| sum + event.delta);
+    let drag = match settings.mode {
+        TiltMode::MouseDrag if buttons.pressed(MouseButton::Left) => Some(dragged),
+        _ => None,
+    };
+
     query.for_each_mut(|iter| {
         let (mut tran, mut comp) = iter;
 
-        comp.angle.x = (comp.angle.x + match (keyboard.pressed(KeyCode::Up), keyboard.pressed(KeyCode::Down)) {
+        if let Some(drag) = drag {
+            // Moving the mouse up the screen pushes the far edge down, like the Up key
+            let invert = if settings.mouse_invert { -1.0 } else { 1.0 };
+            let tilt = invert * settings.mouse_sensitivity * Vec2::new(drag.y, -drag.x);
+            comp.angle = (comp.angle + tilt).clamp(Vec2::splat(-ARENA_MAX_ANGLE), Vec2::splat(ARENA_MAX_ANGLE));
+            tran.rotation = comp.rotation();
+            return;
+        }
+
+        if let Some(stick) = stick {
+            // Pushing the stick forward tips the far edge down, like the Up key
+            let response = stick_response(stick, &settings);
+            let target = -ARENA_MAX_ANGLE * Vec2::new(response.y, response.x);
+            let max_step = ARENA_STICK_SPEED * time.delta_seconds();
+            comp.angle += (target - comp.angle).clamp_length_max(max_step);
+            tran.rotation = comp.rotation();
+            return;
+        }
+
+        comp.angle.x = (comp.angle.x + match (actions.pressed(Action::TiltUp), actions.pressed(Action::TiltDown)) {
             (false, false)  => {-ARENA_ANG_MOMENTUM * comp.angle.x * time.delta_seconds()},
             (false, true)   => {ARENA_ANG_MOMENTUM * time.delta_seconds()},
             (true, false)   => {-ARENA_ANG_MOMENTUM * time.delta_seconds()},
             (true, true)    => {0.0},
         }).clamp(-ARENA_MAX_ANGLE, ARENA_MAX_ANGLE);
 
-        comp.angle.y = (comp.angle.y + match (keyboard.pressed(KeyCode::Right), keyboard.pressed(KeyCode::Left)) {
+        comp.angle.y = (comp.angle.y + match (actions.pressed(Action::TiltRight), actions.pressed(Action::TiltLeft)) {
             (false, false)  => {-ARENA_ANG_MOMENTUM * comp.angle.y * time.delta_seconds()},
             (false, true)   => {ARENA_ANG_MOMENTUM * time.delta_seconds()},
             (true, false)   => {-ARENA_ANG_MOMENTUM * time.delta_seconds()},
             (true, true)    => {0.0},
         }).clamp(-ARENA_MAX_ANGLE, ARENA_MAX_ANGLE);
 
-        tran.rotation = Quat::from_rotation_x(comp.angle.x) * Quat::from_rotation_z(comp.angle.y);
+        tran.rotation = comp.rotation();
     });
 }
 
@@ -109,7 +238,7 @@ fn return_to_neutral(
             }
         }
 
-        tran.rotation = Quat::from_rotation_x(comp.angle.x) * Quat::from_rotation_z(comp.angle.y);
+        tran.rotation = comp.rotation();
     });
 }
 
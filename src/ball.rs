@@ -1,6 +1,8 @@
 use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 use crate::physics::*;
 use crate::arena::*;
+use crate::controls::*;
 
 use super::GameState;
 
@@ -8,6 +10,30 @@ use super::GameState;
 #[derive(Component)]
 pub struct BallComponent {
     pub start_pos: Transform,
+    // Position of the ball in the level file's list of balls
+    pub index: usize,
+    pub state: BallState,
+    // Spin from rolling, stepped with the physics and interpolated like the position
+    pub orientation: Quat,
+    pub prev_orientation: Quat,
+    // How far the ball has dipped below the arena surface over a hole
+    pub sink: f32,
+    // Seconds the ball has been over a hole without resting on the rim
+    pub fall_time: f32,
+}
+
+// Every ball falls and respawns on its own, the others keep rolling meanwhile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BallState {
+    Rolling,
+    // Seconds since it fell into a trap or off the arena
+    Shrinking(f32),
+    // Seconds since it started growing back at its start position
+    Growing(f32),
+    // Falling into the hole at `hole`, out of play once it is at the bottom
+    Dropping { time: f32, hole: Vec2, goal: bool },
+    // Dropped into the final hole, out of play until the next level
+    Sunk,
 }
 
 #[derive(Bundle)]
@@ -18,48 +44,216 @@ pub struct BallBundle {
     pub pbr: PbrBundle,
 }
 
+pub struct BallRes {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<StandardMaterial>,
+    // Checkered so the rolling can be seen, the plain metal hides it
+    pub textured_material: Handle<StandardMaterial>,
+}
+
+impl BallRes {
+    pub fn material(&self, settings: &BallSettings) -> Handle<StandardMaterial> {
+        if settings.textured {
+            self.textured_material.clone()
+        } else {
+            self.material.clone()
+        }
+    }
+}
+
+pub struct BallSettings {
+    pub textured: bool,
+}
+
+impl Default for BallSettings {
+    fn default() -> Self {
+        BallSettings { textured: false }
+    }
+}
+
+const BALL_TEXTURE_WIDTH: u32 = 64;
+const BALL_TEXTURE_HEIGHT: u32 = 32;
+// Checker squares around the equator, half as many from pole to pole
+const BALL_TEXTURE_CHECKS: u32 = 8;
+
+fn ball_texture() -> Image {
+    let check_size = BALL_TEXTURE_WIDTH / BALL_TEXTURE_CHECKS;
+    let mut data = Vec::with_capacity((BALL_TEXTURE_WIDTH * BALL_TEXTURE_HEIGHT * 4) as usize);
+    for y in 0..BALL_TEXTURE_HEIGHT {
+        for x in 0..BALL_TEXTURE_WIDTH {
+            let light = (x / check_size + y / check_size) % 2 == 0;
+            data.extend_from_slice(if light { &[220, 220, 220, 255] } else { &[150, 30, 30, 255] });
+        }
+    }
+
+    Image::new(
+        Extent3d { width: BALL_TEXTURE_WIDTH, height: BALL_TEXTURE_HEIGHT, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+impl FromWorld for BallRes {
+    fn from_world(world: &mut World) -> Self {
+        let mesh_handle = world.resource_mut::<Assets<Mesh>>().add(
+            Mesh::from(shape::UVSphere{
+                radius: BALL_RADIUS,
+                ..default()
+            }));
+
+        let material_handle = world.resource_mut::<Assets<StandardMaterial>>().add(StandardMaterial { 
+            base_color: Color::rgb_u8(200, 200, 200), 
+            metallic: 0.7,
+            ..default()
+        });
+
+        let tex_handle = world.resource_mut::<Assets<Image>>().add(ball_texture());
+
+        let textured_material_handle = world.resource_mut::<Assets<StandardMaterial>>().add(StandardMaterial {
+            base_color_texture: Some(tex_handle),
+            metallic: 0.7,
+            perceptual_roughness: 0.4,
+            ..default()
+        });
+
+        BallRes { mesh: mesh_handle, material: material_handle, textured_material: textured_material_handle }
+    }
+}
+
+pub const BALL_RADIUS: f32 = 0.5;
+
 pub struct BallPlugin;
 
 impl Plugin for BallPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_update(GameState::Running).with_system(ball_movement));
+        app
+            .init_resource::<BallRes>()
+            .init_resource::<BallSettings>()
+            .add_system_set(SystemSet::on_update(GameState::Running).with_system(ball_material_toggle))
+            .add_system(ball_material_update);
+    }
+}
+
+// Switches between the plain metal and the checkered ball
+fn ball_material_toggle(
+    actions: Res<ActionState>,
+    mut settings: ResMut<BallSettings>,
+) {
+    if actions.just_pressed(Action::BallTexture) {
+        settings.textured = !settings.textured;
+    }
+}
+
+fn ball_material_update(
+    settings: Res<BallSettings>,
+    ball_assets: Res<BallRes>,
+    mut ball_query: Query<&mut Handle<StandardMaterial>, With<BallComponent>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for mut material in ball_query.iter_mut() {
+        *material = ball_assets.material(&settings);
     }
 }
 
 pub fn reset_ball(
-    game_state: &mut State<GameState>,
+    ball: &mut BallComponent,
+) {
+    ball.state = BallState::Shrinking(0.0);
+    ball.sink = 0.0;
+    ball.fall_time = 0.0;
+}
+
+// A solid sphere rolling without slipping only gets 5/7 of the slope force,
+// the rest goes into spinning it up
+pub const BALL_ROLLING_FACTOR: f32 = 5.0 / 7.0;
+// Balls bounce off each other without losing energy
+pub const BALL_BOUNCE: f32 = 1.0;
+
+// Accelerates the ball rolling on the tilted arena for one physics step,
+// the movement itself is swept against the obstacles in obstacle_step
+pub fn ball_step(
+    ball_po: &mut PhysicsObject,
+    arena: &Rotator,
+    floor: &PhysicsMaterial,
+    gravity: f32,
+    dt: f32,
+) {
+    ball_po.prev_position = ball_po.position;
+
+    // Gravity seen from the tilted arena, the part along the arena plane
+    // is g * sin(slope) and points downhill, the rest presses on the floor
+    let local_gravity = arena.rotation().inverse() * Vec3::new(0.0, -gravity, 0.0);
+    let slope = Vec2::new(local_gravity.x, local_gravity.z);
+    let pressure = -local_gravity.y;
+
+    // Friction can keep the ball rolling up to tan(slope) = 7/2 * friction,
+    // on steeper or slicker ground it slides and friction only slows it down
+    ball_po.acc = if slope.length() <= 3.5 * floor.friction * pressure {
+        BALL_ROLLING_FACTOR * slope
+    } else {
+        slope - floor.friction * pressure * slope.normalize_or_zero()
+    };
+
+    let resistance = (1.0 - floor.rolling_resistance * dt).max(0.0);
+    ball_po.speed = (ball_po.speed + ball_po.acc * dt) * resistance;
+}
+
+pub fn ball_out_of_bounds(
+    ball_po: &PhysicsObject,
+    arena: &Arena,
+) -> bool {
+    ball_po.position.x < -arena.size / 2.0 || 
+        ball_po.position.x > arena.size / 2.0 ||
+        ball_po.position.y < -arena.size / 2.0 ||
+        ball_po.position.y > arena.size / 2.0
+}
+
+// Turns the ball by the distance it moved over the last step, rolling
+// without slipping: angular velocity = up × v / r. Bounces change the
+// direction of travel and with it the spin.
+pub fn ball_roll(
+    ball_po: &PhysicsObject,
+    ball: &mut BallComponent,
 ) {
-    game_state.set(GameState::RespawnShrink).unwrap();
+    ball.prev_orientation = ball.orientation;
+
+    if let Colider::CircleColider(r) = ball_po.colider {
+        let moved = ball_po.position - ball_po.prev_position;
+        let distance = moved.length();
+        if distance > f32::EPSILON && r > 0.0 {
+            let axis = Vec3::Y.cross(Vec3::new(moved.x, 0.0, moved.y)) / distance;
+            ball.orientation = (Quat::from_axis_angle(axis, distance / r) * ball.orientation).normalize();
+        }
+    }
 }
 
-pub const SPEED_DAMP:f32 = 1.0;
-fn ball_movement(
-    mut _commands: Commands,
-    mut game_state: ResMut<State<GameState>>,
-    time: Res<Time>,
-    mut ball_query: Query<(Entity, &mut Transform, &mut BallComponent, &mut PhysicsObject)>,
-    arena_query: Query<&Rotator>,
+// Separates two overlapping balls and exchanges the speed along the contact
+// normal, both balls weigh the same
+pub fn ball_ball_step(
+    po_a: &mut PhysicsObject,
+    po_b: &mut PhysicsObject,
 ) {
-    let arena = arena_query.single();
-
-    ball_query.for_each_mut(|ball_query_res| {
-        let (_ball_entity, mut ball_transform, _, mut ball_po) = ball_query_res;
-
-        let new_position = Vec2::new(ball_transform.translation.x, ball_transform.translation.z) + ball_po.speed * time.delta_seconds();
-        
-        ball_transform.translation.x = new_position.x;
-        ball_transform.translation.z = new_position.y;
-    
-        ball_po.acc = ball_po.max_acc * Vec2::new(-1.0, 1.0) * Vec2::new(arena.angle.y, arena.angle.x) / ARENA_MAX_ANGLE;
-    
-        ball_po.speed = (ball_po.speed + ball_po.acc * time.delta_seconds()) * SPEED_DAMP;
-    
-        // check if out of bounds
-        if ball_transform.translation.x < -ARENA_SIZE / 2.0 || 
-            ball_transform.translation.x > ARENA_SIZE / 2.0 ||
-            ball_transform.translation.z < -ARENA_SIZE / 2.0 ||
-            ball_transform.translation.z > ARENA_SIZE / 2.0 {
-                reset_ball(&mut game_state);
-            }
-    });
-}
\ No newline at end of file
+    if let (Colider::CircleColider(r_a), Colider::CircleColider(r_b)) = (&po_a.colider, &po_b.colider) {
+        let diff = po_b.position - po_a.position;
+        let distance = diff.length();
+        let overlap = r_a + r_b - distance;
+        if overlap <= 0.0 {
+            return;
+        }
+
+        let normal = if distance > f32::EPSILON { diff / distance } else { Vec2::X };
+        po_a.position -= normal * overlap / 2.0;
+        po_b.position += normal * overlap / 2.0;
+
+        let approach = (po_a.speed - po_b.speed).dot(normal);
+        if approach > 0.0 {
+            let impulse = (1.0 + BALL_BOUNCE) / 2.0 * approach * normal;
+            po_a.speed -= impulse;
+            po_b.speed += impulse;
+        }
+    }
+}
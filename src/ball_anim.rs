@@ -1,85 +1,120 @@
 use bevy::prelude::*;
-use bevy::utils::Instant;
 
-use crate::ball::BallComponent;
+use crate::ball::*;
 use crate::physics::PhysicsObject;
 
 use super::GameState;
 
+// Seconds a ball takes to shrink away or grow back
+const BALL_ANIM_DURATION: f32 = 1.0;
+// Seconds a ball takes to disappear into a hole
+const BALL_DROP_DURATION: f32 = 0.4;
+// Sink at which the ball is completely below the arena surface
+const BALL_DROP_DEPTH: f32 = 2.0 * BALL_RADIUS;
 
 pub struct BallAnimPlugin;
 
-pub struct BallAnimatorAssets {
-    start_time: Option<Instant>,
-}
-
-impl FromWorld for BallAnimatorAssets {
-    fn from_world(_world: &mut World) -> Self {
-
-        BallAnimatorAssets { start_time: None }
-    }
-}
-
 impl Plugin for BallAnimPlugin {
     fn build(&self, app: &mut App) {
         app
-            .init_resource::<BallAnimatorAssets>()
             .add_system_set(SystemSet::on_enter(GameState::RespawnGrow).with_system(ball_anim_grow_enter))
             .add_system_set(SystemSet::on_update(GameState::RespawnGrow).with_system(ball_anim_grow_update))
-            .add_system_set(SystemSet::on_update(GameState::RespawnShrink).with_system(ball_anim_shrink_update));
+            .add_system_set(SystemSet::on_update(GameState::RespawnGrow).with_system(ball_anim_update))
+            .add_system_set(SystemSet::on_update(GameState::RespawnGrow).with_system(ball_anim_grow_done))
+            .add_system_set(SystemSet::on_update(GameState::Running).with_system(ball_anim_update));
     }
 }
 
+fn respawn(ball_component: &mut BallComponent, ball_po: &mut PhysicsObject) {
+    ball_po.teleport(Vec2::new(ball_component.start_pos.translation.x, ball_component.start_pos.translation.z));
+    ball_po.speed = Vec2::ZERO;
+    ball_component.sink = 0.0;
+    ball_component.fall_time = 0.0;
+}
+
+// All balls grow in together when a level starts
 fn ball_anim_grow_enter(
     mut ball_query: Query<(&mut Transform, &mut BallComponent, &mut PhysicsObject)>
 ) {
-    let (mut ball_transform, ball_component, mut ball_po) = ball_query.single_mut();
-    ball_transform.translation = ball_component.start_pos.translation;
-    ball_po.speed = Vec2::ZERO;
+    for (mut ball_transform, mut ball_component, mut ball_po) in ball_query.iter_mut() {
+        respawn(&mut ball_component, &mut ball_po);
+        ball_transform.translation = ball_component.start_pos.translation;
+        ball_transform.scale = Vec3::ZERO;
+        ball_component.state = BallState::Growing(0.0);
+    }
 }
 
+// The physics is not running yet, the animation goes by the frame time
 fn ball_anim_grow_update(
-    mut game_state: ResMut<State<GameState>>,
-    mut ball_anim_a: ResMut<BallAnimatorAssets>,
-    mut ball_query: Query<(&mut Transform, With<BallComponent>)>
+    time: Res<Time>,
+    mut ball_query: Query<(&mut BallComponent, &mut PhysicsObject)>
 ) {
-    let (mut ball_transform, _) = ball_query.single_mut();
-
-    if let None = ball_anim_a.start_time {
-        ball_anim_a.start_time = Some(Instant::now());
-    }
-
-    if let Some(start_instant) = ball_anim_a.start_time {
-        let delta = Instant::now() - start_instant;
-        let scale = (delta.as_secs_f32()).clamp(0.0, 1.0);
-        ball_transform.scale = scale * Vec3::ONE;
-        if scale >= 1.0 {
-            game_state.set(GameState::Running).unwrap();
-            ball_anim_a.start_time = None;
-        }
+    for (mut ball_component, mut ball_po) in ball_query.iter_mut() {
+        ball_anim_step(&mut ball_component, &mut ball_po, time.delta_seconds());
     }
 }
 
-
-fn ball_anim_shrink_update(
+fn ball_anim_grow_done(
     mut game_state: ResMut<State<GameState>>,
-    mut ball_anim_a: ResMut<BallAnimatorAssets>,
-    mut ball_query: Query<(&mut Transform, With<BallComponent>)>
+    ball_query: Query<&BallComponent>,
 ) {
-    let (mut ball_transform, _) = ball_query.single_mut();
-
-    if let None = ball_anim_a.start_time {
-        ball_anim_a.start_time = Some(Instant::now());
+    if ball_query.iter().all(|ball| !matches!(ball.state, BallState::Growing(_))) {
+        game_state.set(GameState::Running).unwrap();
     }
+}
+
+// Drops balls into holes, shrinks balls that rolled off the arena, moves
+// them back to their start and grows them again. While the game is running
+// this is stepped with the physics, so a replay sees the same respawns.
+pub fn ball_anim_step(
+    ball_component: &mut BallComponent,
+    ball_po: &mut PhysicsObject,
+    dt: f32,
+) {
+    ball_component.state = match ball_component.state {
+        BallState::Shrinking(t) if t + dt >= BALL_ANIM_DURATION => {
+            respawn(ball_component, ball_po);
+            BallState::Growing(0.0)
+        },
+        BallState::Shrinking(t) => BallState::Shrinking(t + dt),
+        BallState::Dropping { time, goal: true, .. } if time + dt >= BALL_DROP_DURATION => BallState::Sunk,
+        BallState::Dropping { time, goal: false, .. } if time + dt >= BALL_DROP_DURATION => {
+            respawn(ball_component, ball_po);
+            BallState::Growing(0.0)
+        },
+        BallState::Dropping { time, hole, goal } => BallState::Dropping { time: time + dt, hole, goal },
+        BallState::Growing(t) if t + dt >= BALL_ANIM_DURATION => BallState::Rolling,
+        BallState::Growing(t) => BallState::Growing(t + dt),
+        state => state,
+    };
+}
 
-    if let Some(start_instant) = ball_anim_a.start_time {
-        let delta = Instant::now() - start_instant;
-        let scale = (1.0 - delta.as_secs_f32()).clamp(0.0, 1.0);
+// Places the balls that are out of play, rolling ones are left to the physics
+fn ball_anim_update(
+    mut ball_query: Query<(&mut Transform, &BallComponent, &PhysicsObject)>
+) {
+    for (mut ball_transform, ball_component, ball_po) in ball_query.iter_mut() {
+        let scale = match ball_component.state {
+            BallState::Rolling => {
+                ball_transform.scale = Vec3::ONE;
+                continue;
+            },
+            BallState::Dropping { time, hole, .. } => {
+                // Slides over the middle of the hole while falling faster and faster
+                let progress = (time / BALL_DROP_DURATION).clamp(0.0, 1.0);
+                let position = ball_po.position.lerp(hole, progress);
+                let sink = ball_component.sink + (BALL_DROP_DEPTH - ball_component.sink) * progress * progress;
+                ball_transform.translation = Vec3::new(position.x, BALL_RADIUS - sink, position.y);
+                ball_transform.scale = Vec3::ONE;
+                continue;
+            },
+            BallState::Shrinking(t) => 1.0 - t / BALL_ANIM_DURATION,
+            BallState::Growing(t) => t / BALL_ANIM_DURATION,
+            BallState::Sunk => 0.0,
+        };
+        let scale = scale.clamp(0.0, 1.0);
         ball_transform.scale = scale * Vec3::ONE;
-        if scale <= 0.0 {
-            game_state.set(GameState::RespawnGrow).unwrap();
-            ball_anim_a.start_time = None;
-        }
+        // Keep resting on the (arena space) floor while changing size
+        ball_transform.translation = Vec3::new(ball_po.position.x, BALL_RADIUS * scale, ball_po.position.y);
     }
 }
-
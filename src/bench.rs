@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use bevy::prelude::*;
+
+use crate::ball::BALL_RADIUS;
+use crate::broadphase::*;
+use crate::maze::*;
+use crate::obstacle::*;
+use crate::physics::*;
+
+// Ball positions tested per maze size
+const BENCH_SAMPLES: usize = 20000;
+const BENCH_MAX_SPEED: f32 = 5.0;
+const BENCH_ARENA_SIZES: [f32; 5] = [12.0, 24.0, 48.0, 96.0, 192.0];
+
+// `dipl bench`, times obstacle_step on ever larger generated mazes, once
+// against every wall and once against the broadphase candidates only
+pub fn bench_command(_args: &[String]) -> i32 {
+    println!("{:>8} {:>10} {:>14} {:>14} {:>12}", "walls", "arena", "all (us/step)", "grid (us/step)", "candidates");
+
+    for arena_size in BENCH_ARENA_SIZES {
+        let level = generate_maze(1, arena_size);
+        let walls: Vec<(Transform, PhysicsObject, PhysicsMaterial)> = level.obstacles.iter()
+            .map(|obstacle| (
+                Transform::from_xyz(obstacle.position.0, 0.0, obstacle.position.1)
+                    .with_rotation(obstacle.rotation_quat()),
+                PhysicsObject { colider: obstacle.colider(), ..default() },
+                obstacle.material.physics(),
+            ))
+            .collect();
+        let all: Vec<(&Transform, &PhysicsObject, &PhysicsMaterial)> = walls.iter()
+            .map(|(transform, po, material)| (transform, po, material))
+            .collect();
+
+        let half_size = arena_size / 2.0;
+        let mut grid = SpatialGrid::new(Vec2::splat(-half_size), Vec2::splat(half_size), BROADPHASE_CELL_SIZE);
+        for (i, (transform, po, _)) in walls.iter().enumerate() {
+            let center = Vec2::new(transform.translation.x, transform.translation.z);
+            let reach = Vec2::splat(colider_bounds(&po.colider));
+            grid.insert(i, center - reach, center + reach);
+        }
+
+        // Same balls for both runs
+        let mut rng = MazeRng::new(arena_size as u64);
+        let mut random = |range: f32| (rng.next_u64() % 1_000_000) as f32 / 1_000_000.0 * 2.0 * range - range;
+        let balls: Vec<PhysicsObject> = (0..BENCH_SAMPLES)
+            .map(|_| PhysicsObject {
+                colider: Colider::CircleColider(BALL_RADIUS),
+                position: Vec2::new(random(half_size), random(half_size)),
+                speed: Vec2::new(random(BENCH_MAX_SPEED), random(BENCH_MAX_SPEED)),
+                ..default()
+            })
+            .collect();
+
+        let start = Instant::now();
+        for ball in balls.iter() {
+            let mut ball_po = PhysicsObject { colider: ball.colider.clone(), position: ball.position, speed: ball.speed, ..default() };
+            obstacle_step(&mut ball_po, &all, PHYSICS_TIMESTEP);
+        }
+        let all_time = start.elapsed().as_secs_f64();
+
+        let mut candidates = 0;
+        let start = Instant::now();
+        for ball in balls.iter() {
+            let mut ball_po = PhysicsObject { colider: ball.colider.clone(), position: ball.position, speed: ball.speed, ..default() };
+            let near: Vec<_> = grid
+                .query_around(ball_po.position, obstacle_step_reach(&ball_po, PHYSICS_TIMESTEP))
+                .into_iter()
+                .map(|i| all[i])
+                .collect();
+            candidates += near.len();
+            obstacle_step(&mut ball_po, &near, PHYSICS_TIMESTEP);
+        }
+        let grid_time = start.elapsed().as_secs_f64();
+
+        println!("{:>8} {:>10.0} {:>14.3} {:>14.3} {:>12.1}",
+            walls.len(),
+            arena_size,
+            all_time * 1e6 / BENCH_SAMPLES as f64,
+            grid_time * 1e6 / BENCH_SAMPLES as f64,
+            candidates as f64 / BENCH_SAMPLES as f64);
+    }
+
+    0
+}
@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+
+use crate::arena::*;
+use crate::hole::*;
+use crate::obstacle::*;
+use crate::physics::*;
+
+// Roughly the size of a ball, walls of generated mazes span a few cells
+pub const BROADPHASE_CELL_SIZE: f32 = 1.0;
+
+// Uniform grid over the arena, every item is listed in all cells its
+// bounding box touches. Items outside of the grid end up in the border cells.
+pub struct SpatialGrid<T> {
+    origin: Vec2,
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<T>>,
+}
+
+impl<T: Copy + Ord> SpatialGrid<T> {
+    pub fn new(min: Vec2, max: Vec2, cell_size: f32) -> Self {
+        let columns = (((max.x - min.x) / cell_size).ceil() as usize).max(1);
+        let rows = (((max.y - min.y) / cell_size).ceil() as usize).max(1);
+        SpatialGrid {
+            origin: min,
+            cell_size,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+        }
+    }
+
+    fn cell_range(&self, min: Vec2, max: Vec2) -> (usize, usize, usize, usize) {
+        let clamp = |value: f32, count: usize| (value.floor().max(0.0) as usize).min(count - 1);
+        let low = (min - self.origin) / self.cell_size;
+        let high = (max - self.origin) / self.cell_size;
+        (clamp(low.x, self.columns), clamp(high.x, self.columns), clamp(low.y, self.rows), clamp(high.y, self.rows))
+    }
+
+    pub fn insert(&mut self, item: T, min: Vec2, max: Vec2) {
+        let (x0, x1, y0, y1) = self.cell_range(min, max);
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                self.cells[y * self.columns + x].push(item);
+            }
+        }
+    }
+
+    // Every item whose cells overlap the box, each listed once
+    pub fn query(&self, min: Vec2, max: Vec2) -> Vec<T> {
+        let (x0, x1, y0, y1) = self.cell_range(min, max);
+        let mut items = Vec::new();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                items.extend_from_slice(&self.cells[y * self.columns + x]);
+            }
+        }
+        items.sort_unstable();
+        items.dedup();
+        items
+    }
+
+    pub fn query_around(&self, center: Vec2, radius: f32) -> Vec<T> {
+        self.query(center - Vec2::splat(radius), center + Vec2::splat(radius))
+    }
+}
+
+impl<T: Copy + Ord> Default for SpatialGrid<T> {
+    fn default() -> Self {
+        SpatialGrid::new(Vec2::splat(-ARENA_SIZE / 2.0), Vec2::splat(ARENA_SIZE / 2.0), BROADPHASE_CELL_SIZE)
+    }
+}
+
+// Candidates for the collision checks in physics_step_system,
+// rebuilt whenever an obstacle or a hole is added, moved or removed.
+// Moving obstacles would rebuild it every step, they are checked
+// against every ball instead.
+// Items are keyed by their place in the level file first, entity ids
+// differ between runs and replays have to resolve contacts in the same order.
+#[derive(Default)]
+pub struct Broadphase {
+    pub obstacles: SpatialGrid<(usize, Entity)>,
+    pub holes: SpatialGrid<(usize, Entity)>,
+    pub moving: Vec<(usize, Entity)>,
+}
+
+pub struct BroadphasePlugin;
+
+impl Plugin for BroadphasePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Broadphase>()
+            .add_system(broadphase_update.before(PhysicsLabel::Step));
+    }
+}
+
+// Static obstacles and holes that were added or moved
+type BroadphaseChanged = (
+    Or<(With<ObstacleComponent>, With<HoleComponent>)>,
+    Or<(Changed<Transform>, Added<PhysicsObject>)>,
+    Without<ObstacleMover>,
+);
+
+#[allow(clippy::too_many_arguments)]
+fn broadphase_update(
+    mut broadphase: ResMut<Broadphase>,
+    removed_obstacles: RemovedComponents<ObstacleComponent>,
+    removed_holes: RemovedComponents<HoleComponent>,
+    arena_query: Query<&Arena>,
+    changed_query: Query<(), BroadphaseChanged>,
+    added_movers_query: Query<(), Added<ObstacleMover>>,
+    arena_changed_query: Query<(), Changed<Arena>>,
+    obstacle_query: Query<(Entity, &ObstacleComponent, &Transform, &PhysicsObject, Option<&ObstacleMover>)>,
+    hole_query: Query<(Entity, &HoleComponent, &Transform, &PhysicsObject)>,
+) {
+    let changed = !changed_query.is_empty() ||
+        !added_movers_query.is_empty() ||
+        !arena_changed_query.is_empty() ||
+        removed_obstacles.iter().next().is_some() ||
+        removed_holes.iter().next().is_some();
+    if !changed {
+        return;
+    }
+
+    let half_size = arena_query.iter().next().map_or(ARENA_SIZE, |arena| arena.size) / 2.0;
+    let (min, max) = (Vec2::splat(-half_size), Vec2::splat(half_size));
+
+    let mut obstacles = SpatialGrid::new(min, max, BROADPHASE_CELL_SIZE);
+    let mut moving = Vec::new();
+    for (entity, obstacle, transform, po, mover) in obstacle_query.iter() {
+        if mover.is_some() {
+            moving.push((obstacle.index, entity));
+            continue;
+        }
+        let center = Vec2::new(transform.translation.x, transform.translation.z);
+        let reach = Vec2::splat(colider_bounds(&po.colider));
+        obstacles.insert((obstacle.index, entity), center - reach, center + reach);
+    }
+    moving.sort_unstable();
+
+    let mut holes = SpatialGrid::new(min, max, BROADPHASE_CELL_SIZE);
+    for (entity, hole, transform, po) in hole_query.iter() {
+        let center = Vec2::new(transform.translation.x, transform.translation.z);
+        let reach = Vec2::splat(colider_bounds(&po.colider));
+        holes.insert((hole.index, entity), center - reach, center + reach);
+    }
+
+    *broadphase = Broadphase { obstacles, holes, moving };
+}
@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::input::InputSystem;
+use bevy::input::mouse::MouseWheel;
+use serde::{Deserialize, Serialize};
+
+use crate::level_asset::{assets_dir, load_ron, save_dir, save_ron};
+
+// Everything the player can do during a game, bound to keys and buttons
+// through the InputMap instead of being read directly
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    TiltUp,
+    TiltDown,
+    TiltLeft,
+    TiltRight,
+    SwitchCamera,
+    Pause,
+    Restart,
+    ZoomIn,
+    ZoomOut,
+    BallTexture,
+}
+
+impl Action {
+    pub const ALL: [Action; 10] = [
+        Action::TiltUp,
+        Action::TiltDown,
+        Action::TiltLeft,
+        Action::TiltRight,
+        Action::SwitchCamera,
+        Action::Pause,
+        Action::Restart,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::BallTexture,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::TiltUp => "Tilt up",
+            Action::TiltDown => "Tilt down",
+            Action::TiltLeft => "Tilt left",
+            Action::TiltRight => "Tilt right",
+            Action::SwitchCamera => "Switch camera",
+            Action::Pause => "Pause",
+            Action::Restart => "Restart level",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::BallTexture => "Ball texture",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    // Button on any connected gamepad
+    Gamepad(GamepadButtonType),
+    // Mouse wheel notches, only picked up by actions that read `scrolled`
+    WheelUp,
+    WheelDown,
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{:?}", key),
+            Binding::Mouse(button) => write!(f, "Mouse {:?}", button),
+            Binding::Gamepad(button) => write!(f, "Pad {:?}", button),
+            Binding::WheelUp => write!(f, "Wheel up"),
+            Binding::WheelDown => write!(f, "Wheel down"),
+        }
+    }
+}
+
+// Bindings per action as stored in controls.ron, any number per action
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InputMap {
+    pub bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+// No Default impl, bevy's FromWorld for Default types would skip loading the file
+impl InputMap {
+    pub fn defaults() -> InputMap {
+        let bindings = Action::ALL.iter()
+            .map(|action| (*action, InputMap::default_bindings(*action)))
+            .collect();
+        InputMap { bindings }
+    }
+
+    pub fn default_bindings(action: Action) -> Vec<Binding> {
+        use Binding::*;
+        match action {
+            Action::TiltUp => vec![Key(KeyCode::Up), Key(KeyCode::W), Gamepad(GamepadButtonType::DPadUp)],
+            Action::TiltDown => vec![Key(KeyCode::Down), Key(KeyCode::S), Gamepad(GamepadButtonType::DPadDown)],
+            Action::TiltLeft => vec![Key(KeyCode::Left), Key(KeyCode::A), Gamepad(GamepadButtonType::DPadLeft)],
+            Action::TiltRight => vec![Key(KeyCode::Right), Key(KeyCode::D), Gamepad(GamepadButtonType::DPadRight)],
+            Action::SwitchCamera => vec![Key(KeyCode::C), Gamepad(GamepadButtonType::North)],
+            Action::Pause => vec![Key(KeyCode::Escape), Gamepad(GamepadButtonType::Start)],
+            Action::Restart => vec![Key(KeyCode::R), Gamepad(GamepadButtonType::Select)],
+            Action::ZoomIn => vec![WheelUp, Key(KeyCode::Equals), Gamepad(GamepadButtonType::RightTrigger)],
+            Action::ZoomOut => vec![WheelDown, Key(KeyCode::Minus), Gamepad(GamepadButtonType::LeftTrigger)],
+            Action::BallTexture => vec![Key(KeyCode::T)],
+        }
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    // The player's bindings, written by the settings screen
+    pub fn path() -> PathBuf {
+        save_dir().join("controls.ron")
+    }
+
+    // Bindings the game ships with
+    pub fn asset_path() -> PathBuf {
+        assets_dir().join("controls.ron")
+    }
+
+    // The shipped bindings until the player changes any
+    pub fn load_path() -> PathBuf {
+        let path = InputMap::path();
+        if path.exists() { path } else { InputMap::asset_path() }
+    }
+
+    // Actions missing from the file keep their default bindings
+    pub fn load() -> Result<InputMap, anyhow::Error> {
+        let mut map: InputMap = load_ron(&InputMap::load_path())?;
+        for action in Action::ALL {
+            map.bindings.entry(action).or_insert_with(|| InputMap::default_bindings(action));
+        }
+        Ok(map)
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        save_ron(self, &InputMap::path())
+    }
+}
+
+impl FromWorld for InputMap {
+    fn from_world(_world: &mut World) -> Self {
+        match InputMap::load() {
+            Ok(map) => map,
+            Err(err) => {
+                warn!("Using the default controls, {} can not be read: {}", InputMap::load_path().display(), err);
+                InputMap::defaults()
+            },
+        }
+    }
+}
+
+// Actions held and started this frame, filled in before any game system runs
+#[derive(Default)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    scrolled: HashMap<Action, f32>,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    // Wheel notches turned this frame towards the action
+    pub fn scrolled(&self, action: Action) -> f32 {
+        self.scrolled.get(&action).copied().unwrap_or(0.0)
+    }
+
+    // Keeps what is held, drops what started this frame
+    pub fn clear(&mut self) {
+        self.just_pressed.clear();
+        self.scrolled.clear();
+    }
+}
+
+// A screen opens within the frame of the key press that opened it, so the
+// same press would also act on the screen. Add to on_enter/on_resume of screens
+// to let its first update see nothing new.
+pub fn input_consume(
+    mut keyboard: ResMut<Input<KeyCode>>,
+    mut mouse: ResMut<Input<MouseButton>>,
+    mut gamepad_buttons: ResMut<Input<GamepadButton>>,
+    mut actions: ResMut<ActionState>,
+) {
+    keyboard.clear();
+    mouse.clear();
+    gamepad_buttons.clear();
+    actions.clear();
+}
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<InputMap>()
+            .init_resource::<ActionState>()
+            .add_system_to_stage(CoreStage::PreUpdate, action_state_update.after(InputSystem));
+    }
+}
+
+fn action_state_update(
+    map: Res<InputMap>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut state: ResMut<ActionState>,
+) {
+    let wheel: f32 = wheel_events.iter().map(|event| event.y).sum();
+    let pad_pressed = |button: GamepadButtonType| gamepads.iter()
+        .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(*gamepad, button)));
+    let pad_just_pressed = |button: GamepadButtonType| gamepads.iter()
+        .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(*gamepad, button)));
+
+    let mut next = ActionState::default();
+    for (action, bindings) in map.bindings.iter() {
+        for binding in bindings {
+            let (pressed, just_pressed) = match *binding {
+                Binding::Key(key) => (keyboard.pressed(key), keyboard.just_pressed(key)),
+                Binding::Mouse(button) => (mouse.pressed(button), mouse.just_pressed(button)),
+                Binding::Gamepad(button) => (pad_pressed(button), pad_just_pressed(button)),
+                Binding::WheelUp | Binding::WheelDown => {
+                    let notches = if *binding == Binding::WheelUp { wheel.max(0.0) } else { (-wheel).max(0.0) };
+                    *next.scrolled.entry(*action).or_insert(0.0) += notches;
+                    (false, false)
+                },
+            };
+            if pressed {
+                next.pressed.insert(*action);
+            }
+            if just_pressed {
+                next.just_pressed.insert(*action);
+            }
+        }
+    }
+
+    *state = next;
+}
@@ -0,0 +1,483 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::input::mouse::MouseWheel;
+use bevy::render::camera::Projection;
+
+use crate::arena::*;
+use crate::ball::BALL_RADIUS;
+use crate::level::*;
+use crate::level_asset::*;
+use crate::obstacle::colider_distance;
+use crate::physics::Colider;
+use crate::validate::*;
+
+use super::GameState;
+
+const EDITOR_SNAP: f32 = 0.1;
+const EDITOR_MIN_SIZE: f32 = 0.1;
+const EDITOR_HOLE_PICK_RADIUS: f32 = 0.4;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<EditorRes>()
+            .add_system_set(SystemSet::on_update(GameState::Running).with_system(editor_toggle))
+            .add_system_set(SystemSet::on_enter(GameState::Editor).with_system(editor_enter))
+            .add_system_set(SystemSet::on_update(GameState::Editor).with_system(editor_toggle))
+            .add_system_set(SystemSet::on_update(GameState::Editor).with_system(editor_input))
+            .add_system_set(SystemSet::on_update(GameState::Editor).with_system(editor_rebuild))
+            .add_system_set(SystemSet::on_update(GameState::Editor).with_system(editor_text_update))
+            .add_system_set(SystemSet::on_exit(GameState::Editor).with_system(editor_exit));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditorSelection {
+    // Index into `LevelAsset::ball_starts`
+    Ball(usize),
+    Hole(usize),
+    Obstacle(usize),
+}
+
+#[derive(Default)]
+pub struct EditorRes {
+    level: Option<LevelAsset>,
+    selected: Option<EditorSelection>,
+    dragging: bool,
+    drag_offset: Vec2,
+    dirty: bool,
+    status: String,
+}
+
+#[derive(Component)]
+struct EditorTextComponent;
+
+fn editor_toggle(
+    keyboard: Res<Input<KeyCode>>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    if !keyboard.just_pressed(KeyCode::E) {
+        return;
+    }
+
+    // A restart, pause or finished level queued on the same frame goes first
+    let next = if *game_state.current() == GameState::Editor { GameState::RespawnGrow } else { GameState::Editor };
+    game_state.set(next).ok();
+}
+
+// The arena is moved in the same system, cameras are never part of it
+type TopDownCameraFilter = (With<TopDownCamera>, Without<Arena>);
+
+#[allow(clippy::too_many_arguments)]
+fn editor_enter(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    levels: Res<Assets<LevelAsset>>,
+    level_assets: Res<LevelRes>,
+    mut editor: ResMut<EditorRes>,
+    mut arena_query: Query<(&mut Transform, &mut Rotator), With<Arena>>,
+    mut camera_query: Query<(&mut Camera, Option<&TopDownCamera>)>,
+    mut top_down_query: Query<(&mut Transform, &mut Projection), TopDownCameraFilter>,
+) {
+    let level = match level_assets.current_level().and_then(|handle| levels.get(handle)) {
+        Some(level) => level.clone(),
+        None => return,
+    };
+
+    // Level the board so the top down view matches arena space
+    for (mut transform, mut rotator) in arena_query.iter_mut() {
+        rotator.angle = Vec2::ZERO;
+        transform.rotation = Quat::IDENTITY;
+    }
+
+    for (mut camera, top_down) in camera_query.iter_mut() {
+        camera.is_active = top_down.is_some();
+    }
+
+    for (mut transform, mut projection) in top_down_query.iter_mut() {
+        transform.translation = Vec3::new(0.0, 2.0, 0.0);
+        if let Projection::Orthographic(ortho) = projection.as_mut() {
+            ortho.scale = level.arena_size * 1.1 / 8.0;
+        }
+    }
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/arial.ttf"),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                })
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(5.0),
+                    left: Val::Px(5.0),
+                    ..default()
+                },
+                ..default()
+            }))
+        .insert(EditorTextComponent);
+
+    *editor = EditorRes {
+        level: Some(level),
+        status: String::from("Editing"),
+        ..default()
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+fn editor_exit(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut levels: ResMut<Assets<LevelAsset>>,
+    level_assets: Res<LevelRes>,
+    mut editor: ResMut<EditorRes>,
+    text_query: Query<Entity, With<EditorTextComponent>>,
+    mut camera_query: Query<(&mut Camera, Option<&TopDownCamera>)>,
+    mut top_down_query: Query<&mut Projection, With<TopDownCamera>>,
+) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    for (mut camera, top_down) in camera_query.iter_mut() {
+        camera.is_active = top_down.is_none();
+    }
+
+    for mut projection in top_down_query.iter_mut() {
+        if let Projection::Orthographic(ortho) = projection.as_mut() {
+            ortho.scale = 1.0;
+        }
+    }
+
+    // Keep the edits for the rest of the session even if they were not saved,
+    // as long as they still make a playable level
+    if let (Some(level), Some(handle)) = (editor.level.take(), level_assets.current_level()) {
+        match validate_level(&level) {
+            Ok(()) => {
+                if let Some(asset) = levels.get_mut(handle) {
+                    *asset = level;
+                }
+            },
+            Err(errors) => {
+                match asset_server.get_handle_path(handle) {
+                    Some(asset_path) => warn!("{}", save_draft(&level, asset_path.path(), &errors)),
+                    None => warn!("Edits dropped: {}", format_errors(&errors)),
+                }
+                // Marking the level modified respawns the arena without the edits
+                levels.get_mut(handle);
+            },
+        }
+    }
+}
+
+// A level that fails validation never overwrites its file, it is kept under save/drafts
+fn save_draft(level: &LevelAsset, asset_path: &Path, errors: &[LevelError]) -> String {
+    let draft = save_dir().join("drafts").join(asset_path.file_name().unwrap_or_default());
+    match level.save(&draft) {
+        Ok(()) => format!("Not saved, draft in {}: {}", draft.display(), format_errors(errors)),
+        Err(err) => format!("Not saved, draft failed ({}): {}", err, format_errors(errors)),
+    }
+}
+
+fn cursor_to_arena(
+    windows: &Windows,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+    let window_size = Vec2::new(window.width(), window.height());
+
+    let ndc = (cursor / window_size) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let world = ndc_to_world.project_point3(ndc.extend(-1.0));
+
+    Some(Vec2::new(world.x, world.z))
+}
+
+fn snap(position: Vec2) -> Vec2 {
+    (position / EDITOR_SNAP).round() * EDITOR_SNAP
+}
+
+fn pick(level: &LevelAsset, position: Vec2) -> Option<EditorSelection> {
+    for (i, start) in level.ball_starts().iter().enumerate() {
+        if position.distance(Vec2::new(start.0, start.1)) < BALL_RADIUS {
+            return Some(EditorSelection::Ball(i));
+        }
+    }
+
+    for (i, hole) in level.holes.iter().enumerate() {
+        if position.distance(Vec2::new(hole.position.0, hole.position.1)) < EDITOR_HOLE_PICK_RADIUS {
+            return Some(EditorSelection::Hole(i));
+        }
+    }
+
+    for (i, obstacle) in level.obstacles.iter().enumerate() {
+        let diff = position - Vec2::new(obstacle.position.0, obstacle.position.1);
+        let local = obstacle.rotation_quat().inverse() * Vec3::new(diff.x, 0.0, diff.y);
+        if colider_distance(&obstacle.colider(), Vec2::new(local.x, local.z)) <= 0.0 {
+            return Some(EditorSelection::Obstacle(i));
+        }
+    }
+
+    None
+}
+
+fn selection_position(level: &LevelAsset, selection: EditorSelection) -> Vec2 {
+    match selection {
+        EditorSelection::Ball(i) => {
+            let start = level.ball_starts()[i];
+            Vec2::new(start.0, start.1)
+        },
+        EditorSelection::Hole(i) => Vec2::new(level.holes[i].position.0, level.holes[i].position.1),
+        EditorSelection::Obstacle(i) => Vec2::new(level.obstacles[i].position.0, level.obstacles[i].position.1),
+    }
+}
+
+// Grows the obstacle by `step` along x, or along z with `depth`. Boxes change
+// their size (and their explicit colider with it), capsules their length
+// (their radius with `depth`), circles and polygons keep their shape and
+// scale as a whole.
+fn resize_obstacle(obstacle: &mut ObstacleDef, step: f32, depth: bool) {
+    // Factor that grows `extent` by `step`, but not below the smallest size
+    let scale = |extent: f32| (extent + step).max(EDITOR_MIN_SIZE) / extent.max(f32::EPSILON);
+    let grow = |extent: &mut f32| *extent = (*extent + step).max(EDITOR_MIN_SIZE);
+
+    match &mut obstacle.colider {
+        None => {
+            grow(if depth { &mut obstacle.size.1 } else { &mut obstacle.size.0 });
+        },
+        Some(Colider::BoxColider(w, h)) => {
+            // The mesh is the unit box scaled by the size, both grow together
+            if depth {
+                grow(h);
+                grow(&mut obstacle.size.1);
+            } else {
+                grow(w);
+                grow(&mut obstacle.size.0);
+            }
+        },
+        Some(Colider::CircleColider(radius)) => {
+            *radius *= scale(*radius * 2.0);
+        },
+        Some(Colider::CapsuleColider(_, _, radius)) if depth => {
+            *radius *= scale(*radius * 2.0);
+        },
+        Some(Colider::CapsuleColider(a, b, _)) => {
+            let (a_point, b_point) = (Vec2::new(a.0, a.1), Vec2::new(b.0, b.1));
+            let center = (a_point + b_point) / 2.0;
+            let factor = scale(a_point.distance(b_point));
+            let (a_point, b_point) = (center + (a_point - center) * factor, center + (b_point - center) * factor);
+            *a = (a_point.x, a_point.y);
+            *b = (b_point.x, b_point.y);
+        },
+        Some(Colider::PolygonColider(points)) => {
+            let (min, max) = points.iter().fold((Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)), |(min, max), point| {
+                (min.min(Vec2::new(point.0, point.1)), max.max(Vec2::new(point.0, point.1)))
+            });
+            let center = (min + max) / 2.0;
+            let factor = scale((max - min).max_element());
+            for point in points.iter_mut() {
+                let scaled = center + (Vec2::new(point.0, point.1) - center) * factor;
+                *point = (scaled.x, scaled.y);
+            }
+        },
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn editor_input(
+    windows: Res<Windows>,
+    buttons: Res<Input<MouseButton>>,
+    keyboard: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    level_assets: Res<LevelRes>,
+    mut scroll_evr: EventReader<MouseWheel>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<TopDownCamera>>,
+    mut editor: ResMut<EditorRes>,
+) {
+    let editor = editor.as_mut();
+    let level = match editor.level.as_mut() {
+        Some(level) => level,
+        None => return,
+    };
+
+    let shift = keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift);
+    let cursor = match camera_query.get_single() {
+        Ok((camera, camera_transform)) => cursor_to_arena(&windows, camera, camera_transform),
+        Err(_) => None,
+    };
+
+    if let Some(cursor) = cursor {
+        let position = snap(cursor);
+
+        if keyboard.just_pressed(KeyCode::O) {
+            level.obstacles.push(ObstacleDef {
+                position: (position.x, position.y),
+                size: (1.0, 0.4),
+                rotation: 0.0,
+                colider: None,
+                material: default(),
+                motion: None,
+            });
+            editor.selected = Some(EditorSelection::Obstacle(level.obstacles.len() - 1));
+            editor.dirty = true;
+        }
+
+        if keyboard.just_pressed(KeyCode::H) {
+            level.holes.push(HoleDef {
+                position: (position.x, position.y),
+                is_final: false,
+            });
+            editor.selected = Some(EditorSelection::Hole(level.holes.len() - 1));
+            editor.dirty = true;
+        }
+
+        // B moves the first ball, Shift+B adds another one
+        if keyboard.just_pressed(KeyCode::B) {
+            if shift {
+                level.extra_balls.push((position.x, position.y));
+                editor.selected = Some(EditorSelection::Ball(level.extra_balls.len()));
+            } else {
+                level.ball_start = (position.x, position.y);
+                editor.selected = Some(EditorSelection::Ball(0));
+            }
+            editor.dirty = true;
+        }
+
+        if buttons.just_pressed(MouseButton::Left) {
+            editor.selected = pick(level, cursor);
+            editor.dragging = editor.selected.is_some();
+            if let Some(selection) = editor.selected {
+                editor.drag_offset = selection_position(level, selection) - cursor;
+            }
+        }
+
+        if buttons.pressed(MouseButton::Left) && editor.dragging {
+            if let Some(selection) = editor.selected {
+                let target = snap(cursor + editor.drag_offset);
+                if target != selection_position(level, selection) {
+                    match selection {
+                        EditorSelection::Ball(i) => level.set_ball_start(i, (target.x, target.y)),
+                        EditorSelection::Hole(i) => level.holes[i].position = (target.x, target.y),
+                        EditorSelection::Obstacle(i) => level.obstacles[i].position = (target.x, target.y),
+                    }
+                    editor.dirty = true;
+                }
+            }
+        }
+    }
+
+    if buttons.just_released(MouseButton::Left) {
+        editor.dragging = false;
+    }
+
+    // Wheel resizes the selected obstacle, width by default and depth with shift
+    for ev in scroll_evr.iter() {
+        if let Some(EditorSelection::Obstacle(i)) = editor.selected {
+            resize_obstacle(&mut level.obstacles[i], ev.y * EDITOR_SNAP, shift);
+            editor.dirty = true;
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::M) {
+        if let Some(EditorSelection::Obstacle(i)) = editor.selected {
+            level.obstacles[i].material = level.obstacles[i].material.next();
+            editor.dirty = true;
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::F) {
+        if let Some(EditorSelection::Hole(i)) = editor.selected {
+            level.holes[i].is_final = !level.holes[i].is_final;
+            editor.dirty = true;
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::Delete) || keyboard.just_pressed(KeyCode::Back) {
+        match editor.selected.take() {
+            // The first ball can only be moved
+            Some(EditorSelection::Ball(0)) => {},
+            Some(EditorSelection::Ball(i)) => { level.extra_balls.remove(i - 1); },
+            Some(EditorSelection::Hole(i)) => { level.holes.remove(i); },
+            Some(EditorSelection::Obstacle(i)) => { level.obstacles.remove(i); },
+            None => {},
+        }
+        editor.dragging = false;
+        editor.dirty = true;
+    }
+
+    let ctrl = keyboard.pressed(KeyCode::LControl) || keyboard.pressed(KeyCode::RControl);
+    if ctrl && keyboard.just_pressed(KeyCode::S) {
+        let asset_path = level_assets.current_level()
+            .and_then(|handle| asset_server.get_handle_path(handle));
+        editor.status = match asset_path {
+            Some(asset_path) => {
+                let path = assets_dir().join(asset_path.path());
+                match validate_level(level) {
+                    Ok(()) => match level.save(&path) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(err) => format!("Save failed: {}", err),
+                    },
+                    Err(errors) => save_draft(level, asset_path.path(), &errors),
+                }
+            },
+            None => String::from("Save failed: level has no file"),
+        };
+        info!("{}", editor.status);
+    }
+}
+
+// Respawns the arena from the edited level whenever something changed
+fn editor_rebuild(
+    mut editor: ResMut<EditorRes>,
+    mut level_assets: ResMut<LevelRes>,
+    mut level_builder: LevelBuilder,
+) {
+    if !editor.dirty {
+        return;
+    }
+    editor.dirty = false;
+
+    if let Some(level) = editor.level.as_ref() {
+        if let Some(arena) = level_assets.arena {
+            level_builder.despawn(arena);
+        }
+        level_assets.arena = Some(level_builder.spawn(level, &[]));
+    }
+}
+
+fn editor_text_update(
+    editor: Res<EditorRes>,
+    mut query: Query<&mut Text, With<EditorTextComponent>>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+
+    let selected = match (editor.selected, editor.level.as_ref()) {
+        (Some(EditorSelection::Ball(i)), Some(_)) =>
+            format!("ball {}", i),
+        (Some(EditorSelection::Hole(i)), Some(level)) =>
+            format!("hole {} (final: {})", i, level.holes[i].is_final),
+        (Some(EditorSelection::Obstacle(i)), Some(level)) =>
+            format!("obstacle {} ({:.1} x {:.1}, {:?})", i, level.obstacles[i].size.0, level.obstacles[i].size.1, level.obstacles[i].material),
+        _ => String::from("nothing"),
+    };
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!(
+            "EDITOR - {}\nSelected: {}\n\
+            LMB select / drag, Wheel resize (Shift: box depth, capsule radius)\n\
+            O obstacle, H hole, B ball start (Shift: extra ball), F toggle final, M material\n\
+            Del delete, Ctrl+S save, E play",
+            editor.status, selected);
+    }
+}
@@ -0,0 +1,112 @@
+use bevy::prelude::*;
+
+use crate::arena::*;
+use crate::ball::*;
+use crate::physics::*;
+use crate::replay::*;
+
+use super::GameState;
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<GhostRes>()
+            .add_system_set(SystemSet::on_update(GameState::Running).with_system(ghost_spawn))
+            .add_system_set(SystemSet::on_update(GameState::Running).with_system(ghost_update.after(PhysicsLabel::Step)));
+    }
+}
+
+pub struct GhostRes {
+    pub material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for GhostRes {
+    fn from_world(world: &mut World) -> Self {
+        let material = world.resource_mut::<Assets<StandardMaterial>>().add(StandardMaterial {
+            base_color: Color::rgba(0.6, 0.8, 1.0, 0.35),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        });
+
+        GhostRes { material }
+    }
+}
+
+// Shows where a ball was at the same step of the ghost run, it has no
+// PhysicsObject so the live balls roll straight through it
+#[derive(Component)]
+pub struct GhostComponent {
+    // Ball of the ghost run it follows
+    pub index: usize,
+}
+
+// The ghosts are children of the arena, so they go with it on a restart, a hot
+// reload or the editor and come back with the new one. They follow the step
+// of the attempt, which starts the race over with every attempt.
+fn ghost_spawn(
+    mut commands: Commands,
+    replay: Res<ReplayRes>,
+    ghost_assets: Res<GhostRes>,
+    ball_assets: Res<BallRes>,
+    arena_query: Query<Entity, With<Arena>>,
+    ghost_query: Query<Entity, With<GhostComponent>>,
+) {
+    if !ghost_query.is_empty() {
+        return;
+    }
+
+    let (ghost, arena) = match (replay.ghost(), arena_query.get_single()) {
+        (Some(ghost), Ok(arena)) => (ghost, arena),
+        _ => return,
+    };
+    let balls = ghost.balls.first().map_or(0, Vec::len);
+
+    commands.entity(arena).with_children(|parent| {
+        for index in 0..balls {
+            parent
+                .spawn_bundle(PbrBundle {
+                    mesh: ball_assets.mesh.clone(),
+                    material: ghost_assets.material.clone(),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(GhostComponent { index });
+        }
+    });
+}
+
+// Steps are matched one to one with the live attempt, so the ghost is
+// interpolated between the same two steps as the balls
+fn ghost_update(
+    clock: Res<PhysicsClock>,
+    replay: Res<ReplayRes>,
+    mut ghost_query: Query<(&mut Transform, &mut Visibility, &GhostComponent)>,
+) {
+    let ghost = match replay.ghost() {
+        Some(ghost) => ghost,
+        None => return,
+    };
+    let position_at = |step: usize, index: usize| replay.step.checked_sub(step)
+        .and_then(|step| ghost.balls.get(step))
+        .and_then(|balls| balls.get(index).copied().flatten())
+        .map(|(x, y)| Vec2::new(x, y));
+
+    for (mut transform, mut visibility, ghost_ball) in ghost_query.iter_mut() {
+        // Hidden before the first step, while the ball was out of play and after the run ended
+        let position = match position_at(1, ghost_ball.index) {
+            Some(position) => position,
+            None => {
+                visibility.is_visible = false;
+                continue;
+            },
+        };
+        let prev_position = position_at(2, ghost_ball.index).unwrap_or(position);
+        let position = prev_position.lerp(position, clock.alpha);
+
+        transform.translation = Vec3::new(position.x, BALL_RADIUS, position.y);
+        visibility.is_visible = true;
+    }
+}
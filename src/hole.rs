@@ -1,13 +1,13 @@
 use bevy::prelude::*;
 
-use super::GameState;
-
 use crate::physics::*;
 use crate::ball::*;
 
 #[derive(Component, Default)]
 pub struct HoleComponent {
     pub is_final: bool,
+    // Place in the level file, see ObstacleComponent::index
+    pub index: usize,
 } 
 
 pub struct HoleRes {
@@ -118,50 +118,77 @@ pub struct HolePlugin;
 impl Plugin for HolePlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_system_set(SystemSet::on_update(GameState::Running).with_system(hole_system))
             .init_resource::<HoleRes>();
     }
 }
 
-fn hole_system(
-    mut game_state: ResMut<State<GameState>>,
-    mut ball_query: Query<(&mut Transform, &mut PhysicsObject, &BallComponent)>,
-    mut hole_query: Query<(&mut Transform, &mut PhysicsObject, &HoleComponent, Without<BallComponent>)>,
-) {
-    let mut ball_iterator = ball_query.iter_mut();
-    // Grab all ball entitys
-    while let Some(mut ball) = ball_iterator.next()  {
-        let mut other_iter = hole_query.iter_mut();
-        // Grab other coliders
-        while let Some(other) = other_iter.next()  {
-
-            {
-                let tr_a = ball.0.as_mut();
-                let po_a = ball.1.as_mut();
-                let _ball_component = ball.2;
-                let tr_b = other.0;
-                let po_b = other.1;
-                let hc = other.2;
-                if let Colider::CircleColider(ball_r) = po_a.colider {
-                    if let Colider::CircleColider(hole_r) = po_b.colider {
-                        // BALL vs HOLE
-                        let center_distance = ((tr_a.translation.x - tr_b.translation.x).powi(2) + (tr_a.translation.z - tr_b.translation.z).powi(2)).sqrt();
-                        if center_distance < ball_r + hole_r {
-                            // hole collision
-                            if hc.is_final {
-                                // is a final hole
-                                game_state.set(GameState::Splash).unwrap();
-
-                            } else {
-                                // is not a final
-                                reset_ball(&mut game_state);
-                                return;
-                            }
-
-                        }
-                    }
-                }
+pub enum HoleOutcome {
+    Goal,
+    Trap,
+}
+
+// How far the ball falls while crossing the opening before the far rim
+// can no longer catch it
+const HOLE_CATCH_DEPTH: f32 = 0.1;
+
+// The opening of a hole is wide enough for the ball to pass once its
+// center is within `hole_r` of the hole center, so the rim sits at
+// `ball_r + hole_r`. Between the two the ball rests on the rim, dips in
+// and is pulled toward the center, further in it is unsupported and falls
+// in unless it is fast enough to reach the far rim first.
+// Returns the hole and the outcome once the ball has dropped in.
+pub fn hole_step(
+    ball_po: &mut PhysicsObject,
+    ball: &mut BallComponent,
+    holes: &[(&Transform, &PhysicsObject, &HoleComponent)],
+    gravity: f32,
+    dt: f32,
+) -> Option<(HoleOutcome, Vec2)> {
+    let ball_r = match ball_po.colider {
+        Colider::CircleColider(ball_r) => ball_r,
+        _ => return None,
+    };
+
+    // Nearest hole whose opening the ball is over
+    let mut over: Option<(Vec2, f32, f32, bool)> = None;
+    for &(hole_transform, hole_po, hc) in holes {
+        if let Colider::CircleColider(hole_r) = hole_po.colider {
+            let center = Vec2::new(hole_transform.translation.x, hole_transform.translation.z);
+            let distance = ball_po.position.distance(center);
+            if distance < ball_r + hole_r && over.map_or(true, |(_, best, _, _)| distance < best) {
+                over = Some((center, distance, hole_r, hc.is_final));
             }
         }
     }
-}
\ No newline at end of file
+
+    let (center, distance, hole_r, is_final) = match over {
+        Some(over) => over,
+        None => {
+            ball.sink = 0.0;
+            ball.fall_time = 0.0;
+            return None;
+        },
+    };
+
+    let rim_offset = (ball_r + hole_r - distance).min(ball_r);
+    if distance > hole_r {
+        // Resting on the rim, tipped by rim_offset / ball_r toward the center
+        let tip = rim_offset / ball_r;
+        let inward = (center - ball_po.position).normalize_or_zero();
+        ball_po.speed += inward * BALL_ROLLING_FACTOR * gravity * tip * dt;
+        ball.sink = ball_r - (ball_r * ball_r - rim_offset * rim_offset).max(0.0).sqrt();
+        ball.fall_time = 0.0;
+        return None;
+    }
+
+    // Nothing holds the ball up any more
+    ball.fall_time += dt;
+    let fallen = 0.5 * gravity * ball.fall_time * ball.fall_time;
+    ball.sink = ball_r + fallen;
+    if fallen < HOLE_CATCH_DEPTH {
+        return None;
+    }
+
+    let outcome = if is_final { HoleOutcome::Goal } else { HoleOutcome::Trap };
+    Some((outcome, center))
+}
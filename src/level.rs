@@ -1,6 +1,8 @@
 use bevy::prelude::*;
-use bevy::input::mouse::{MouseMotion, MouseWheel};
+use bevy::asset::LoadState;
+use bevy::input::mouse::MouseMotion;
 use bevy::render::camera::ScalingMode;
+use bevy::ecs::system::SystemParam;
 
 use crate::arena::*;
 use crate::hole::*;
@@ -8,25 +10,55 @@ use crate::physics;
 use crate::physics::PhysicsObject;
 use crate::ball::*;
 use crate::obstacle::*;
+use crate::level_asset::*;
+use crate::maze::*;
+use crate::surface::*;
+use crate::controls::*;
+use crate::replay::*;
+use crate::validate::level_file_problem;
+
+use super::GameState;
 
 pub struct LevelPlugin;
 
+// Change of the orbit camera distance per wheel notch and per second of a held zoom key
+const CAMERA_ZOOM_STEP: f32 = 0.1;
+const CAMERA_ZOOM_SPEED: f32 = 5.0;
+const CAMERA_MIN_DISTANCE: f32 = 1.0;
+
 impl Plugin for LevelPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_asset::<LevelAsset>()
+            .add_asset::<CampaignAsset>()
+            .init_asset_loader::<LevelLoader>()
+            .init_asset_loader::<CampaignLoader>()
+            .init_resource::<LevelRes>()
+            .init_resource::<LevelReloadSettings>()
+            .add_system(level_list_system)
+            .add_system(level_hot_reload_system)
             .add_startup_system(startup_system)
+            .add_system_set(SystemSet::on_enter(GameState::Loading).with_system(level_unload_system))
+            .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(level_unload_system))
+            .add_system_set(SystemSet::on_update(GameState::Loading).with_system(level_loading_system))
+            .add_system_set(SystemSet::on_enter(GameState::LevelComplete).with_system(level_complete_system.label(LevelCompleteLabel)))
+            .add_system_set(SystemSet::on_update(GameState::Running).with_system(level_restart_system))
             .add_system(mouse_motion)
             .add_system(orbit_camera_startup)
             .add_system(camera_switch)
             .add_system(move_top_down_camera)
-            .add_system(mouse_scroll);
+            .add_system(camera_zoom);
     }
 }
 
+// Moves on from a finished level, systems that look at the finished level run before it
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LevelCompleteLabel;
+
 #[derive(Component)]
-struct MovableCamera {
-    focus_distance: f32,
-    focus: Vec3,
+pub struct MovableCamera {
+    pub focus_distance: f32,
+    pub focus: Vec3,
 }
 
 impl Default for MovableCamera {
@@ -39,8 +71,8 @@ impl Default for MovableCamera {
 }
 
 #[derive(Component)]
-struct TopDownCamera {
-    focus_distance: f32,
+pub struct TopDownCamera {
+    pub focus_distance: f32,
 }
 
 impl Default for TopDownCamera {
@@ -51,296 +83,452 @@ impl Default for TopDownCamera {
     }
 }
 
-// Accessing resources using Res/ResMut
-// Accessing components of entities using queries(Query)
-// Creating/destroying entities, components, and resources using Commands(Commands)
-// Sending/receiving events using EventWriter/EventReader
-fn startup_system(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    _asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    arena_assets: Res<ArenaRes>,
-    hole_assets: Res<HoleRes>,
+// Where the played levels come from, picked from the command line
+#[derive(Clone, Debug)]
+pub enum LevelSource {
+    Campaign(String),
+    // Endless generated mazes, starting from the given seed
+    Practice(u64),
+    // One generated maze shared by everyone on the same day
+    Daily,
+    // The level stored in the replay given with --replay or --ghost
+    Replay,
+}
+
+impl Default for LevelSource {
+    fn default() -> Self {
+        LevelSource::Campaign(String::from("levels/main.campaign.ron"))
+    }
+}
+
+pub struct LevelRes {
+    pub source: LevelSource,
+    pub campaign: Handle<CampaignAsset>,
+    pub levels: Vec<Handle<LevelAsset>>,
+    pub current: usize,
+    pub arena: Option<Entity>,
+    // Why the last level could not be loaded, shown on the main menu
+    pub load_error: Option<String>,
+}
+
+impl FromWorld for LevelRes {
+    fn from_world(world: &mut World) -> Self {
+        let source = world.get_resource::<LevelSource>().cloned().unwrap_or_default();
+        let campaign = match &source {
+            LevelSource::Campaign(path) => world.resource::<AssetServer>().load(path.as_str()),
+            _ => Handle::default(),
+        };
+
+        LevelRes { source, campaign, levels: Vec::new(), current: 0, arena: None, load_error: None }
+    }
+}
+
+impl LevelRes {
+    pub fn current_level(&self) -> Option<&Handle<LevelAsset>> {
+        self.levels.get(self.current)
+    }
+
+    pub fn is_last_level(&self) -> bool {
+        self.current + 1 >= self.levels.len()
+    }
+
+    pub fn current_key(&self, asset_server: &AssetServer) -> Option<String> {
+        self.key(self.current, asset_server)
+    }
+
+    // Name the player's records of a level are filed under,
+    // None for levels that only come from a replay
+    pub fn key(&self, level: usize, asset_server: &AssetServer) -> Option<String> {
+        match &self.source {
+            LevelSource::Campaign(_) => {
+                let path = asset_server.get_handle_path(self.levels.get(level)?)?;
+                let name = path.path().file_name()?.to_str()?;
+                Some(name.trim_end_matches(".level.ron").to_string())
+            },
+            // Every following practice maze uses the next seed
+            LevelSource::Practice(seed) => Some(format!("practice-{}", seed.wrapping_add(level as u64))),
+            LevelSource::Daily => Some(format!("daily-{}", daily_seed())),
+            LevelSource::Replay => None,
+        }
+    }
+}
+
+// Fills in the levels of the source as soon as they are known, the level
+// select screen lists them before any is played
+fn level_list_system(
+    asset_server: Res<AssetServer>,
+    campaigns: Res<Assets<CampaignAsset>>,
+    mut levels: ResMut<Assets<LevelAsset>>,
+    mut level_assets: ResMut<LevelRes>,
+    replay: Res<ReplayRes>,
 ) {
-    let cube_material_handle = materials.add(StandardMaterial { 
-        //base_color: Color::RED, 
-        base_color_texture: Some(arena_assets.tex.clone()),
-        metallic: 0.0,
-        reflectance: 0.0,
-        perceptual_roughness: 1.0,
-        ..default()
-    });
+    if !level_assets.levels.is_empty() {
+        return;
+    }
 
-    let hole_material_handle = materials.add(StandardMaterial { 
-        //base_color: Color::RED, 
-        base_color_texture: Some(hole_assets.tex.clone()),
-        metallic: 0.0,
-        reflectance: 0.0,
-        perceptual_roughness: 1.0,
-        alpha_mode: AlphaMode::Mask(0.5),
-        ..default()
-    });
+    match level_assets.source.clone() {
+        LevelSource::Campaign(_) => {
+            if let Some(campaign) = campaigns.get(&level_assets.campaign) {
+                level_assets.levels = campaign.levels.iter()
+                    .map(|path| asset_server.load(path.as_str()))
+                    .collect();
+            }
+        },
+        LevelSource::Practice(seed) => {
+            level_assets.levels.push(levels.add(generate_maze(seed, ARENA_SIZE)));
+            info!("Practice maze, seed {}", seed);
+        },
+        LevelSource::Daily => {
+            let seed = daily_seed();
+            level_assets.levels.push(levels.add(generate_maze(seed, ARENA_SIZE)));
+            info!("Daily maze, seed {}", seed);
+        },
+        LevelSource::Replay => {
+            if let Some(playback) = replay.playback.as_ref().or(replay.ghost_file.as_ref()) {
+                level_assets.levels.push(levels.add(playback.level.clone()));
+                info!("Playing back a replay of {:.2}s", playback.duration());
+            }
+        },
+    }
+}
 
-    let final_hole_material_handle = materials.add(StandardMaterial { 
-        //base_color: Color::RED, 
-        base_color_texture: Some(hole_assets.final_tex.clone()),
-        metallic: 0.0,
-        reflectance: 0.0,
-        perceptual_roughness: 1.0,
-        alpha_mode: AlphaMode::Mask(0.5),
-        ..default()
-    });
+// Waits for the level file to load, spawns it and starts the game
+// once the spawned arena is visible to queries (next frame)
+fn level_loading_system(
+    mut game_state: ResMut<State<GameState>>,
+    asset_server: Res<AssetServer>,
+    levels: Res<Assets<LevelAsset>>,
+    mut level_assets: ResMut<LevelRes>,
+    mut level_builder: LevelBuilder,
+    arena_query: Query<Entity, With<Arena>>,
+) {
+    if let Some(arena) = level_assets.arena {
+        if arena_query.get(arena).is_ok() {
+            game_state.set(GameState::RespawnGrow).unwrap();
+        }
+        return;
+    }
 
-    let ball_material_handle = materials.add(StandardMaterial { 
-        base_color: Color::rgb_u8(200, 200, 200), 
-        metallic: 0.7,
-        ..default()
-    });
+    // A broken file never loads, back to the menu with the reason
+    if let Some(error) = level_load_error(&asset_server, &level_assets) {
+        error!("{}", error);
+        level_assets.load_error = Some(error);
+        game_state.set(GameState::MainMenu).unwrap();
+        return;
+    }
 
-    let ball_handle = meshes.add(Mesh::from(shape::UVSphere{
-        radius: 0.5,
-        ..default()
-    }));
-
-    let box_handle = meshes.add(Mesh::from(shape::Box{
-        max_x: 0.5,
-        max_y: 1.0,
-        max_z: 0.5,
-        min_x:-0.5,
-        min_y: 0.0,
-        min_z:-0.5,
-    }));
-
-    // parent cube
-    commands
-        .spawn_bundle(ArenaBundle {
-            rotator: Rotator { angle: Vec2::ZERO },
-            pbr: PbrBundle {
-                mesh: arena_assets.mesh.clone(),
-                material: cube_material_handle.clone(),
-                transform: Transform::from_xyz(0.0, 0.0, 0.0),
+    let level = level_assets.current_level().and_then(|handle| levels.get(handle));
+    if let Some(level) = level {
+        let arena = level_builder.spawn(level, &[]);
+        level_assets.arena = Some(arena);
+        level_assets.load_error = None;
+    }
+}
+
+// The loader only logs why a file failed, the level file is checked again for the message
+fn level_load_error(asset_server: &AssetServer, level_assets: &LevelRes) -> Option<String> {
+    if let LevelSource::Campaign(path) = &level_assets.source {
+        if level_assets.levels.is_empty() && asset_server.get_load_state(&level_assets.campaign) == LoadState::Failed {
+            return Some(format!("Campaign {} can not be read", path));
+        }
+    }
+
+    let handle = level_assets.current_level()?;
+    if asset_server.get_load_state(handle) != LoadState::Failed {
+        return None;
+    }
+    let path = asset_server.get_handle_path(handle)?.path().to_path_buf();
+    let problem = level_file_problem(&assets_dir().join(&path)).unwrap_or_else(|| String::from("can not be loaded"));
+    Some(format!("Level {}: {}", path.display(), problem))
+}
+
+// Moves on from the finished level to the next one, or to the splash
+// screen at the end of the campaign
+fn level_complete_system(
+    mut game_state: ResMut<State<GameState>>,
+    mut levels: ResMut<Assets<LevelAsset>>,
+    mut level_assets: ResMut<LevelRes>,
+) {
+    // Practice never runs out of levels
+    if let LevelSource::Practice(seed) = level_assets.source.clone() {
+        if level_assets.is_last_level() {
+            let next_seed = seed.wrapping_add(level_assets.levels.len() as u64);
+            level_assets.levels.push(levels.add(generate_maze(next_seed, ARENA_SIZE)));
+            info!("Practice maze, seed {}", next_seed);
+        }
+    }
+
+    if level_assets.is_last_level() {
+        game_state.set(GameState::Splash).unwrap();
+        return;
+    }
+
+    level_assets.current += 1;
+    game_state.set(GameState::Loading).unwrap();
+}
+
+// Builds the current level again from its file, balls and moving obstacles
+// start over
+fn level_restart_system(
+    actions: Res<ActionState>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    if actions.just_pressed(Action::Restart) {
+        // Pausing, the editor or a finished level queued on the same frame go first
+        game_state.set(GameState::Loading).ok();
+    }
+}
+
+// Whatever arena is still up goes away on the way to another level or the menu
+fn level_unload_system(
+    mut level_builder: LevelBuilder,
+    mut level_assets: ResMut<LevelRes>,
+) {
+    if let Some(arena) = level_assets.arena.take() {
+        level_builder.despawn(arena);
+    }
+}
+
+// Hot reload of the level file that is currently being played
+fn level_hot_reload_system(
+    mut asset_events: EventReader<AssetEvent<LevelAsset>>,
+    game_state: Res<State<GameState>>,
+    levels: Res<Assets<LevelAsset>>,
+    reload_settings: Res<LevelReloadSettings>,
+    mut level_assets: ResMut<LevelRes>,
+    mut level_builder: LevelBuilder,
+    ball_query: Query<(&Transform, &BallComponent, &PhysicsObject)>,
+) {
+    for event in asset_events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            // The editor keeps its own copy of the level while it is open
+            if level_assets.current_level() != Some(handle) ||
+                *game_state.current() == GameState::Loading ||
+                *game_state.current() == GameState::Editor {
+                continue;
+            }
+
+            if let (Some(arena), Some(level)) = (level_assets.arena, levels.get(handle)) {
+                let mut kept_balls = Vec::new();
+                if reload_settings.keep_ball_position {
+                    let mut balls: Vec<_> = ball_query.iter().collect();
+                    balls.sort_by_key(|(_, ball, _)| ball.index);
+                    kept_balls = balls.into_iter().map(|(transform, ball, po)| KeptBall::new(transform, ball, po)).collect();
+                }
+
+                level_builder.despawn(arena);
+                level_assets.arena = Some(level_builder.spawn(level, &kept_balls));
+                info!("Level {} reloaded", level_assets.current + 1);
+            }
+        }
+    }
+}
+
+// --reload-reset-balls puts every ball back to its start on a hot reload
+pub struct LevelReloadSettings {
+    pub keep_ball_position: bool,
+}
+
+impl Default for LevelReloadSettings {
+    fn default() -> Self {
+        LevelReloadSettings { keep_ball_position: true }
+    }
+}
+
+// A ball carried over a hot reload, falling, sunk and moving balls carry on
+// where they were. The start position comes from the reloaded level.
+pub struct KeptBall {
+    pub transform: Transform,
+    pub state: BallState,
+    pub sink: f32,
+    pub fall_time: f32,
+    pub position: Vec2,
+    pub prev_position: Vec2,
+    pub speed: Vec2,
+}
+
+impl KeptBall {
+    pub fn new(transform: &Transform, ball: &BallComponent, po: &PhysicsObject) -> Self {
+        KeptBall {
+            transform: *transform,
+            state: ball.state,
+            sink: ball.sink,
+            fall_time: ball.fall_time,
+            position: po.position,
+            prev_position: po.prev_position,
+            speed: po.speed,
+        }
+    }
+}
+
+// Everything needed to build or tear down an arena hierarchy
+#[derive(SystemParam)]
+pub struct LevelBuilder<'w, 's> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    ball_assets: Res<'w, BallRes>,
+    ball_settings: Res<'w, BallSettings>,
+    hole_assets: Res<'w, HoleRes>,
+    obstacle_assets: Res<'w, ObstacleRes>,
+    surface_assets: Res<'w, SurfaceRes>,
+}
+
+impl<'w, 's> LevelBuilder<'w, 's> {
+    pub fn despawn(&mut self, arena: Entity) {
+        self.commands.entity(arena).despawn_recursive();
+    }
+
+    // Spawns the arena with all of its children, `kept_balls` override
+    // the balls from the level file by index (used when reloading)
+    pub fn spawn(&mut self, level: &LevelAsset, kept_balls: &[KeptBall]) -> Entity {
+        let ball_starts = level.ball_starts();
+        let balls_to_win = level.balls_to_win().clamp(1, ball_starts.len());
+        let ball_assets = &self.ball_assets;
+        let ball_material = self.ball_assets.material(&self.ball_settings);
+        let hole_assets = &self.hole_assets;
+        let obstacle_assets = &self.obstacle_assets;
+        let surface_assets = &self.surface_assets;
+        let arena_mesh_handle = self.meshes.add(arena_mesh(level.arena_size));
+        let obstacle_meshes: Vec<Option<Handle<Mesh>>> = level.obstacles.iter()
+            .map(|obstacle| obstacle_mesh(&obstacle.colider(), OBSTACLE_HEIGHT).map(|mesh| self.meshes.add(mesh)))
+            .collect();
+        let zone_meshes: Vec<Handle<Mesh>> = level.zones.iter()
+            .map(|zone| self.meshes.add(prism_mesh(&colider_outline(&zone.colider()), ZONE_HEIGHT)))
+            .collect();
+
+        // parent cube
+        self.commands
+            .spawn_bundle(ArenaBundle {
+                arena: Arena { size: level.arena_size, balls_to_win },
+                rotator: Rotator { angle: Vec2::ZERO },
+                pbr: PbrBundle {
+                    mesh: arena_mesh_handle,
+                    material: surface_assets.material(&level.floor),
+                    transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                    ..default()
+                },
                 ..default()
-            },
-            ..default()
-        })
-        .with_children(|parent| {
-            // child cube 
-            // BALL
-            parent.spawn_bundle(
-                BallBundle {
-                    ball_comp: BallComponent {
-                        start_pos: Transform::from_xyz(-5.0, 0.5, -5.0),
-                    },
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::new(1.0, 1.0),
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::CircleColider(0.5)
-                    },
-                    pbr: PbrBundle {
-                        mesh: ball_handle,
-                        material: ball_material_handle.clone(),
-                        transform: Transform::from_xyz(-5.0, 0.5, -5.0),
-                        ..default()
-                    }
-                });
-            
-            // HOLES
-            parent.spawn_bundle(
-                HoleBundle {
-                    hole_comp: HoleComponent { is_final: true },
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::new(1.0, 1.0),
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::CircleColider(0.15)
-                    },
-                    pbr: PbrBundle {
-                        mesh: hole_assets.mesh.clone(),
-                        material: final_hole_material_handle.clone(),
-                        transform: Transform::from_xyz(5.0, 0.001, 5.0),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                HoleBundle {
-                    hole_comp: HoleComponent { is_final: false },
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::new(1.0, 1.0),
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::CircleColider(0.15)
-                    },
-                    pbr: PbrBundle {
-                        mesh: hole_assets.mesh.clone(),
-                        material: hole_material_handle.clone(),
-                        transform: Transform::from_xyz(3.0, 0.001, -3.0),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                HoleBundle {
-                    hole_comp: HoleComponent { is_final: false },
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::new(1.0, 1.0),
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::CircleColider(0.15)
-                    },
-                    pbr: PbrBundle {
-                        mesh: hole_assets.mesh.clone(),
-                        material: hole_material_handle.clone(),
-                        transform: Transform::from_xyz(-3.5, 0.001, -2.8),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                HoleBundle {
-                    hole_comp: HoleComponent { is_final: false },
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::new(1.0, 1.0),
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::CircleColider(0.15)
-                    },
-                    pbr: PbrBundle {
-                        mesh: hole_assets.mesh.clone(),
-                        material: hole_material_handle.clone(),
-                        transform: Transform::from_xyz(0.0, 0.001, 1.8),
-                        ..default()
-                    }
-                });
-
-
-            parent.spawn_bundle(
-                HoleBundle {
-                    hole_comp: HoleComponent { is_final: false },
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::new(1.0, 1.0),
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::CircleColider(0.15)
-                    },
-                    pbr: PbrBundle {
-                        mesh: hole_assets.mesh.clone(),
-                        material: hole_material_handle.clone(),
-                        transform: Transform::from_xyz(-1.5, 0.001, 3.8),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                HoleBundle {
-                    hole_comp: HoleComponent { is_final: false },
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::new(1.0, 1.0),
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::CircleColider(0.15)
-                    },
-                    pbr: PbrBundle {
-                        mesh: hole_assets.mesh.clone(),
-                        material: hole_material_handle.clone(),
-                        transform: Transform::from_xyz(2.6, 0.001, 4.2),
-                        ..default()
-                    }
-                });
-            // OBSTACLES
-            parent.spawn_bundle(
-                ObstacleBundle {
-                    obstacle_comp: ObstacleComponent,
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::ZERO,
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::BoxColider(0.4, 8.0)
-                    },
-                    pbr: PbrBundle {
-                        mesh: box_handle.clone(),
-                        material: cube_material_handle.clone(),
-                        transform: Transform::from_xyz(-5.8, 0.0, 0.0)
-                            .with_scale(Vec3::new(0.4, 1.0, 8.0)),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                ObstacleBundle {
-                    obstacle_comp: ObstacleComponent,
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::ZERO,
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::BoxColider(0.4, 1.5)
-                    },
-                    pbr: PbrBundle {
-                        mesh: box_handle.clone(),
-                        material: cube_material_handle.clone(),
-                        transform: Transform::from_xyz(-3.5, 0.0, -4.5)
-                            .with_scale(Vec3::new(0.4, 1.0, 1.5)),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                ObstacleBundle {
-                    obstacle_comp: ObstacleComponent,
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::ZERO,
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::BoxColider(0.4, 6.0)
-                    },
-                    pbr: PbrBundle {
-                        mesh: box_handle.clone(),
-                        material: cube_material_handle.clone(),
-                        transform: Transform::from_xyz(-3.5, 0.0, 2.5)
-                            .with_scale(Vec3::new(0.4, 1.0, 6.0)),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                ObstacleBundle {
-                    obstacle_comp: ObstacleComponent,
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::ZERO,
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::BoxColider(1.0, 4.0)
-                    },
-                    pbr: PbrBundle {
-                        mesh: box_handle.clone(),
-                        material: cube_material_handle.clone(),
-                        transform: Transform::from_xyz(4.0, 0.0, 0.0)
-                            .with_scale(Vec3::new(1.0, 1.0, 4.0)),
-                        ..default()
-                    }
-                });
-
-            parent.spawn_bundle(
-                ObstacleBundle {
-                    obstacle_comp: ObstacleComponent,
-                    po: PhysicsObject {
-                        acc: Vec2::ZERO,
-                        max_acc: Vec2::ZERO,
-                        speed: Vec2::ZERO,
-                        colider: physics::Colider::BoxColider(4.0, 0.4)
-                    },
-                    pbr: PbrBundle {
-                        mesh: box_handle.clone(),
-                        material: cube_material_handle.clone(),
-                        transform: Transform::from_xyz(1.5, 0.0, 0.0)
-                            .with_scale(Vec3::new(4.0, 1.0, 0.4)),
-                        ..default()
+            })
+            .insert(level.floor.physics())
+            .with_children(|parent| {
+                // BALLS
+                for (i, start) in ball_starts.iter().enumerate() {
+                    let ball_start = Transform::from_xyz(start.0, BALL_RADIUS, start.1);
+                    let kept = kept_balls.get(i);
+                    let ball_transform = kept.map_or(ball_start, |kept| kept.transform);
+                    let position = Vec2::new(ball_transform.translation.x, ball_transform.translation.z);
+                    parent.spawn_bundle(
+                        BallBundle {
+                            ball_comp: BallComponent {
+                                start_pos: ball_start,
+                                index: i,
+                                state: kept.map_or(BallState::Rolling, |kept| kept.state),
+                                orientation: ball_transform.rotation,
+                                prev_orientation: ball_transform.rotation,
+                                sink: kept.map_or(0.0, |kept| kept.sink),
+                                fall_time: kept.map_or(0.0, |kept| kept.fall_time),
+                            },
+                            po: PhysicsObject {
+                                acc: Vec2::ZERO,
+                                speed: kept.map_or(Vec2::ZERO, |kept| kept.speed),
+                                colider: physics::Colider::CircleColider(BALL_RADIUS),
+                                position: kept.map_or(position, |kept| kept.position),
+                                prev_position: kept.map_or(position, |kept| kept.prev_position),
+                                ..default()
+                            },
+                            pbr: PbrBundle {
+                                mesh: ball_assets.mesh.clone(),
+                                material: ball_material.clone(),
+                                transform: ball_transform,
+                                ..default()
+                            }
+                        });
+                }
+
+                // HOLES
+                for (index, hole) in level.holes.iter().enumerate() {
+                    parent.spawn_bundle(
+                        HoleBundle {
+                            hole_comp: HoleComponent { is_final: hole.is_final, index },
+                            po: PhysicsObject {
+                                acc: Vec2::ZERO,
+                                speed: Vec2::ZERO,
+                                colider: physics::Colider::CircleColider(HOLE_RADIUS),
+                                ..default()
+                            },
+                            pbr: PbrBundle {
+                                mesh: hole_assets.mesh.clone(),
+                                material: if hole.is_final {
+                                    hole_assets.final_hole_material_handle.clone()
+                                } else {
+                                    hole_assets.hole_material_handle.clone()
+                                },
+                                transform: Transform::from_xyz(hole.position.0, 0.001, hole.position.1),
+                                ..default()
+                            }
+                        });
+                }
+
+                // OBSTACLES
+                for (index, (obstacle, mesh)) in level.obstacles.iter().zip(obstacle_meshes).enumerate() {
+                    let transform = match mesh {
+                        Some(_) => Transform::from_xyz(obstacle.position.0, 0.0, obstacle.position.1),
+                        None => Transform::from_xyz(obstacle.position.0, 0.0, obstacle.position.1)
+                            .with_scale(Vec3::new(obstacle.size.0, OBSTACLE_HEIGHT, obstacle.size.1)),
+                    };
+                    let mut entity = parent.spawn_bundle(
+                        ObstacleBundle {
+                            obstacle_comp: ObstacleComponent { index },
+                            po: PhysicsObject {
+                                acc: Vec2::ZERO,
+                                speed: Vec2::ZERO,
+                                colider: obstacle.colider(),
+                                ..default()
+                            },
+                            material: obstacle.material.physics(),
+                            pbr: PbrBundle {
+                                mesh: mesh.unwrap_or_else(|| obstacle_assets.mesh.clone()),
+                                material: surface_assets.material(&obstacle.material),
+                                transform: transform.with_rotation(obstacle.rotation_quat()),
+                                ..default()
+                            }
+                        });
+                    if let Some(motion) = &obstacle.motion {
+                        entity.insert(ObstacleMover::new(
+                            motion.clone(),
+                            Vec2::new(obstacle.position.0, obstacle.position.1),
+                            obstacle.rotation.to_radians(),
+                            &obstacle.colider()));
                     }
-                });
-        });
-    
+                }
+
+                // FLOOR ZONES
+                for (i, (zone, mesh)) in level.zones.iter().zip(zone_meshes).enumerate() {
+                    parent.spawn_bundle(
+                        ZoneBundle {
+                            zone_comp: ZoneComponent { order: i },
+                            po: PhysicsObject {
+                                colider: zone.colider(),
+                                ..default()
+                            },
+                            material: zone.material.physics(),
+                            pbr: PbrBundle {
+                                mesh,
+                                material: surface_assets.material(&zone.material),
+                                transform: Transform::from_xyz(zone.position.0, i as f32 * ZONE_LAYER_STEP, zone.position.1)
+                                    .with_rotation(zone.rotation_quat()),
+                                ..default()
+                            }
+                        });
+                }
+            })
+            .id()
+    }
+}
+
+fn startup_system(
+    mut commands: Commands,
+) {
     // light
     commands.spawn_bundle(PointLightBundle {
         transform: Transform::from_xyz(3.0, 3.0, -3.0),
@@ -386,12 +574,12 @@ fn startup_system(
 // CAMERA SWITCH
 fn camera_switch(
     _commands: Commands,
-    keyboard: Res<Input<KeyCode>>,
+    actions: Res<ActionState>,
     //mut active_cameras: ResMut<bevy::render::camera::<Camera3d>>,
     mut ortho_camera_query: Query<(Entity, &mut Camera, With<TopDownCamera>)>,
     mut persp_camera_query: Query<(Entity, &mut Camera, Without<TopDownCamera>)>,
 ) {
-    if keyboard.just_pressed(KeyCode::C) {
+    if actions.just_pressed(Action::SwitchCamera) {
         if persp_camera_query.single().1.is_active == true {
             //active_cameras.set(ortho_camera_query.single().0);
             ortho_camera_query.single_mut().1.is_active = true;
@@ -417,15 +605,21 @@ fn mouse_motion(
     }
 }
 
-fn mouse_scroll(
-    mut mouse_evr: EventReader<MouseWheel>,
+// Wheel notches move the camera in steps, held keys and buttons move it smoothly
+fn camera_zoom(
+    time: Res<Time>,
+    actions: Res<ActionState>,
     mut query: Query<(&mut Transform, &mut MovableCamera)>,
 ) {
+    let held = actions.pressed(Action::ZoomOut) as i32 - actions.pressed(Action::ZoomIn) as i32;
+    let zoom = (actions.scrolled(Action::ZoomOut) - actions.scrolled(Action::ZoomIn)) * CAMERA_ZOOM_STEP +
+        held as f32 * CAMERA_ZOOM_SPEED * time.delta_seconds();
+    if zoom == 0.0 {
+        return;
+    }
 
-    for ev in mouse_evr.iter() {
-        for (_cam_transform, mut cam_comp) in query.iter_mut() {
-            cam_comp.focus_distance += ev.y * 0.1;
-        }
+    for (_cam_transform, mut cam_comp) in query.iter_mut() {
+        cam_comp.focus_distance = (cam_comp.focus_distance + zoom).max(CAMERA_MIN_DISTANCE);
     }
 }
 
@@ -449,13 +643,28 @@ fn move_orbit_camera(
 }
 
 fn move_top_down_camera(
+    game_state: Res<State<GameState>>,
     ball_query: Query<(&GlobalTransform, &BallComponent)>,
     mut query: Query<(&mut Transform, &mut TopDownCamera)>,
 ) {
-    let (ball_trasform, _ball_comp) = ball_query.single();
+    // The editor positions the camera over the whole arena
+    if *game_state.current() == GameState::Editor {
+        return;
+    }
+
+    // Follow the middle of the balls that are still in play
+    let balls: Vec<Vec3> = ball_query.iter()
+        .filter(|(_, ball_comp)| ball_comp.state != BallState::Sunk)
+        .map(|(ball_trasform, _)| ball_trasform.translation())
+        .collect();
+    if balls.is_empty() {
+        return;
+    }
+    let focus = balls.iter().sum::<Vec3>() / balls.len() as f32;
+
     for (mut camera, camera_comp) in query.iter_mut() {
-        camera.translation.x = ball_trasform.translation().x;
-        camera.translation.z = ball_trasform.translation().z;
-        camera.translation.y = camera_comp.focus_distance + ball_trasform.translation().y;
+        camera.translation.x = focus.x;
+        camera.translation.z = focus.z;
+        camera.translation.y = camera_comp.focus_distance + focus.y;
     }
 }
\ No newline at end of file
@@ -0,0 +1,201 @@
+use bevy::prelude::*;
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use ron::ser::PrettyConfig;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::arena::ARENA_SIZE;
+use crate::obstacle::ObstacleMotion;
+use crate::physics::Colider;
+use crate::surface::Surface;
+use crate::validate::*;
+
+pub const HOLE_RADIUS: f32 = 0.15;
+pub const OBSTACLE_HEIGHT: f32 = 1.0;
+
+// Level description as it is stored on disk (assets/levels/*.level.ron).
+// Positions are (x, z) in arena space, the arena is centered at the origin.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "3f6b7a0e-2c1d-4e8b-9a55-6d0c2f1e8b47"]
+pub struct LevelAsset {
+    #[serde(default = "default_arena_size")]
+    pub arena_size: f32,
+    pub ball_start: (f32, f32),
+    // Start positions of any further balls rolling at the same time
+    #[serde(default)]
+    pub extra_balls: Vec<(f32, f32)>,
+    // Balls that have to reach the final hole, all of them when omitted
+    #[serde(default)]
+    pub balls_to_win: Option<usize>,
+    #[serde(default)]
+    pub holes: Vec<HoleDef>,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleDef>,
+    // Surface of the arena wherever there is no zone
+    #[serde(default)]
+    pub floor: Surface,
+    #[serde(default)]
+    pub zones: Vec<ZoneDef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HoleDef {
+    pub position: (f32, f32),
+    #[serde(default)]
+    pub is_final: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObstacleDef {
+    pub position: (f32, f32),
+    // Width (x) and depth (z) of the wall, only used by box coliders
+    #[serde(default)]
+    pub size: (f32, f32),
+    // Rotation about the up axis in degrees
+    #[serde(default)]
+    pub rotation: f32,
+    // When omitted a box colider matching `size` is used
+    #[serde(default)]
+    pub colider: Option<Colider>,
+    #[serde(default)]
+    pub material: Surface,
+    // Sweepers and sliding gates, static when omitted (zones never move)
+    #[serde(default)]
+    pub motion: Option<ObstacleMotion>,
+}
+
+// Floor zones are laid out like obstacles, the ball rolls over them
+// instead of bouncing off
+pub type ZoneDef = ObstacleDef;
+
+fn default_arena_size() -> f32 {
+    ARENA_SIZE
+}
+
+impl LevelAsset {
+    // `ball_start` followed by the extra balls
+    pub fn ball_starts(&self) -> Vec<(f32, f32)> {
+        std::iter::once(self.ball_start).chain(self.extra_balls.iter().copied()).collect()
+    }
+
+    pub fn set_ball_start(&mut self, ball: usize, position: (f32, f32)) {
+        match ball {
+            0 => self.ball_start = position,
+            i => self.extra_balls[i - 1] = position,
+        }
+    }
+
+    pub fn balls_to_win(&self) -> usize {
+        self.balls_to_win.unwrap_or(1 + self.extra_balls.len())
+    }
+
+    // Writes the level back to disk in the same format the loader reads
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        save_ron(self, path)
+    }
+}
+
+// Mirrors how the file asset io resolves the "assets" folder
+pub fn assets_dir() -> PathBuf {
+    base_dir().join("assets")
+}
+
+// Personal bests and other player data, next to the assets
+pub fn save_dir() -> PathBuf {
+    base_dir().join("save")
+}
+
+// Settings, saves and replays are all RON files read and written outside of the asset server
+pub fn load_ron<T: DeserializeOwned>(path: &Path) -> Result<T, anyhow::Error> {
+    let bytes = std::fs::read(path)?;
+    Ok(ron::de::from_bytes(&bytes)?)
+}
+
+// Creates the folder first, nothing is saved before the first run
+pub fn save_ron<T: Serialize>(value: &T, path: &Path) -> Result<(), anyhow::Error> {
+    let contents = ron::ser::to_string_pretty(value, PrettyConfig::new())?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+fn base_dir() -> PathBuf {
+    if let Ok(manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") {
+        PathBuf::from(manifest_dir)
+    } else {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+            .unwrap_or_default()
+    }
+}
+
+impl ObstacleDef {
+    pub fn rotation_quat(&self) -> Quat {
+        Quat::from_rotation_y(self.rotation.to_radians())
+    }
+
+    pub fn colider(&self) -> Colider {
+        match &self.colider {
+            Some(colider) => colider.clone(),
+            None => Colider::BoxColider(self.size.0, self.size.1),
+        }
+    }
+}
+
+// Ordered list of level files played one after another
+// (assets/levels/*.campaign.ron)
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "9c1e4b2a-7d3f-4a60-8e21-5b7f0c9d3a18"]
+pub struct CampaignAsset {
+    pub levels: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let level: LevelAsset = ron::de::from_bytes(bytes)?;
+            if let Err(errors) = validate_level(&level) {
+                return Err(anyhow::anyhow!("invalid level: {}", format_errors(&errors)));
+            }
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+#[derive(Default)]
+pub struct CampaignLoader;
+
+impl AssetLoader for CampaignLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let campaign: CampaignAsset = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(campaign));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["campaign.ron"]
+    }
+}
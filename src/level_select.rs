@@ -0,0 +1,359 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::ball::BALL_RADIUS;
+use crate::controls::input_consume;
+use crate::level::*;
+use crate::level_asset::*;
+use crate::obstacle::colider_outline;
+use crate::replay::ReplayRes;
+use crate::score::*;
+
+use super::GameState;
+
+const THUMBNAIL_SIZE: u32 = 96;
+// Drawn size of the holes, the opening with its rim rather than the collision radius
+const THUMBNAIL_HOLE_RADIUS: f32 = 0.4;
+const LEVEL_SELECT_COLUMNS: usize = 4;
+
+pub struct LevelSelectPlugin;
+
+impl Plugin for LevelSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Progress>()
+            .init_resource::<LevelSelectRes>()
+            .add_system_set(SystemSet::on_enter(GameState::LevelComplete).with_system(progress_complete.before(LevelCompleteLabel)))
+            .add_system_set(SystemSet::on_enter(GameState::LevelSelect).with_system(level_select_enter).with_system(input_consume))
+            .add_system_set(SystemSet::on_update(GameState::LevelSelect).with_system(level_select_spawn))
+            .add_system_set(SystemSet::on_update(GameState::LevelSelect).with_system(level_select_thumbnails))
+            .add_system_set(SystemSet::on_update(GameState::LevelSelect).with_system(level_select_input))
+            .add_system_set(SystemSet::on_update(GameState::LevelSelect).with_system(level_select_update))
+            .add_system_set(SystemSet::on_exit(GameState::LevelSelect).with_system(level_select_exit));
+    }
+}
+
+// Levels finished at least once as stored in save/progress.ron, by the
+// same keys as the leaderboard
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Progress {
+    pub completed: BTreeSet<String>,
+}
+
+impl Progress {
+    pub fn path() -> PathBuf {
+        save_dir().join("progress.ron")
+    }
+
+    pub fn load() -> Result<Progress, anyhow::Error> {
+        load_ron(&Progress::path())
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        save_ron(self, &Progress::path())
+    }
+
+    // Campaign levels open up one after another, the first one and every
+    // level that was finished before stay open. Generated mazes are all open.
+    pub fn is_unlocked(&self, level: usize, level_assets: &LevelRes, asset_server: &AssetServer) -> bool {
+        if !matches!(level_assets.source, LevelSource::Campaign(_)) || level == 0 {
+            return true;
+        }
+        [level - 1, level].iter()
+            .filter_map(|level| level_assets.key(*level, asset_server))
+            .any(|key| self.completed.contains(&key))
+    }
+}
+
+impl FromWorld for Progress {
+    fn from_world(_world: &mut World) -> Self {
+        // Nothing is saved before the first level is finished
+        Progress::load().unwrap_or_else(|_| Progress { completed: BTreeSet::new() })
+    }
+}
+
+#[derive(Default)]
+pub struct LevelSelectRes {
+    selected: usize,
+}
+
+#[derive(Component)]
+struct LevelSelectRootComponent;
+
+#[derive(Component)]
+struct LevelCellComponent {
+    level: usize,
+}
+
+#[derive(Component)]
+struct LevelThumbnailComponent {
+    level: usize,
+    drawn: bool,
+}
+
+#[derive(Component)]
+struct LevelCellTextComponent {
+    level: usize,
+}
+
+// Top-down picture of the board: floor, zones, walls, holes and ball starts
+pub fn level_thumbnail(level: &LevelAsset) -> Image {
+    let placed = |def: &ObstacleDef| -> Vec<Vec2> {
+        let rotation = def.rotation_quat();
+        colider_outline(&def.colider()).iter()
+            .map(|point| rotation * Vec3::new(point.x, 0.0, point.y))
+            .map(|point| Vec2::new(def.position.0 + point.x, def.position.1 + point.z))
+            .collect()
+    };
+    // Later shapes are drawn over earlier ones
+    let mut shapes: Vec<(Vec<Vec2>, Color)> = Vec::new();
+    shapes.extend(level.zones.iter().map(|zone| (placed(zone), zone.material.color())));
+    shapes.extend(level.obstacles.iter().map(|obstacle| (placed(obstacle), obstacle.material.color() * 0.5)));
+    let circles: Vec<(Vec2, f32, Color)> = level.holes.iter()
+        .map(|hole| {
+            let color = if hole.is_final { Color::GOLD } else { Color::BLACK };
+            (Vec2::new(hole.position.0, hole.position.1), THUMBNAIL_HOLE_RADIUS, color)
+        })
+        .chain(level.ball_starts().iter().map(|start| (Vec2::new(start.0, start.1), BALL_RADIUS, Color::WHITE)))
+        .collect();
+
+    let mut data = Vec::with_capacity((THUMBNAIL_SIZE * THUMBNAIL_SIZE * 4) as usize);
+    for y in 0..THUMBNAIL_SIZE {
+        for x in 0..THUMBNAIL_SIZE {
+            // Rows go from the far (-z) edge to the near one, as seen from the top-down camera
+            let point = (Vec2::new(x as f32, y as f32) + 0.5) / THUMBNAIL_SIZE as f32 * level.arena_size - level.arena_size / 2.0;
+            let mut color = level.floor.color();
+            for (outline, shape_color) in shapes.iter() {
+                if polygon_contains(outline, point) {
+                    color = *shape_color;
+                }
+            }
+            for (center, radius, circle_color) in circles.iter() {
+                if point.distance(*center) <= *radius {
+                    color = *circle_color;
+                }
+            }
+            data.extend_from_slice(&color.as_rgba_u32().to_le_bytes());
+        }
+    }
+
+    Image::new(
+        Extent3d { width: THUMBNAIL_SIZE, height: THUMBNAIL_SIZE, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+// Even-odd rule, works for the concave outlines too
+fn polygon_contains(outline: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    for i in 0..outline.len() {
+        let (a, b) = (outline[i], outline[(i + 1) % outline.len()]);
+        if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn progress_complete(
+    mut progress: ResMut<Progress>,
+    asset_server: Res<AssetServer>,
+    level_assets: Res<LevelRes>,
+    replay: Res<ReplayRes>,
+) {
+    if replay.is_playing() {
+        return;
+    }
+
+    if let Some(key) = level_assets.current_key(&asset_server) {
+        if progress.completed.insert(key) {
+            if let Err(err) = progress.save() {
+                error!("Progress could not be saved: {}", err);
+            }
+        }
+    }
+}
+
+fn level_select_enter(
+    mut select: ResMut<LevelSelectRes>,
+    level_assets: Res<LevelRes>,
+) {
+    *select = LevelSelectRes { selected: level_assets.current };
+}
+
+fn level_select_exit(
+    mut commands: Commands,
+    root_query: Query<Entity, With<LevelSelectRootComponent>>,
+) {
+    for entity in root_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// The grid is built once the campaign file has been read
+fn level_select_spawn(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level_assets: Res<LevelRes>,
+    root_query: Query<Entity, With<LevelSelectRootComponent>>,
+) {
+    if !root_query.is_empty() || level_assets.levels.is_empty() {
+        return;
+    }
+
+    let font: Handle<Font> = asset_server.load("fonts/arial.ttf");
+    let text_style = |font_size: f32| TextStyle { font: font.clone(), font_size, color: Color::WHITE };
+    let levels = level_assets.levels.len();
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                // Children are laid out from the top
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: UiColor(Color::NONE),
+            ..default()
+        })
+        .insert(LevelSelectRootComponent)
+        .with_children(|root| {
+            root.spawn_bundle(TextBundle::from_section("SELECT LEVEL", text_style(60.0))
+                .with_style(Style { margin: UiRect::all(Val::Px(20.0)), ..default() }));
+
+            for row in 0..(levels + LEVEL_SELECT_COLUMNS - 1) / LEVEL_SELECT_COLUMNS {
+                root.spawn_bundle(NodeBundle { color: UiColor(Color::NONE), ..default() })
+                    .with_children(|row_node| {
+                        for level in (row * LEVEL_SELECT_COLUMNS..levels).take(LEVEL_SELECT_COLUMNS) {
+                            row_node
+                                .spawn_bundle(NodeBundle {
+                                    style: Style {
+                                        flex_direction: FlexDirection::ColumnReverse,
+                                        align_items: AlignItems::Center,
+                                        padding: UiRect::all(Val::Px(8.0)),
+                                        margin: UiRect::all(Val::Px(4.0)),
+                                        ..default()
+                                    },
+                                    color: UiColor(Color::NONE),
+                                    ..default()
+                                })
+                                .insert(LevelCellComponent { level })
+                                .with_children(|cell| {
+                                    cell.spawn_bundle(ImageBundle {
+                                        style: Style {
+                                            size: Size::new(Val::Px(THUMBNAIL_SIZE as f32), Val::Px(THUMBNAIL_SIZE as f32)),
+                                            ..default()
+                                        },
+                                        ..default()
+                                    })
+                                    .insert(LevelThumbnailComponent { level, drawn: false });
+                                    cell.spawn_bundle(TextBundle::from_section("", text_style(16.0)))
+                                        .insert(LevelCellTextComponent { level });
+                                });
+                        }
+                    });
+            }
+
+            root.spawn_bundle(TextBundle::from_section("Arrows: select   Enter: play   Esc: back", text_style(16.0))
+                .with_style(Style { margin: UiRect::all(Val::Px(20.0)), ..default() }));
+        });
+}
+
+// Drawn as the level files come in
+fn level_select_thumbnails(
+    mut images: ResMut<Assets<Image>>,
+    levels: Res<Assets<LevelAsset>>,
+    level_assets: Res<LevelRes>,
+    mut thumbnail_query: Query<(&mut UiImage, &mut LevelThumbnailComponent)>,
+) {
+    for (mut image, mut thumbnail) in thumbnail_query.iter_mut() {
+        if thumbnail.drawn {
+            continue;
+        }
+        if let Some(level) = level_assets.levels.get(thumbnail.level).and_then(|handle| levels.get(handle)) {
+            image.0 = images.add(level_thumbnail(level));
+            thumbnail.drawn = true;
+        }
+    }
+}
+
+// Arrows or the gamepad's d-pad move through the grid, Enter or the south
+// button starts an unlocked level, Esc or the east button goes back
+#[allow(clippy::too_many_arguments)]
+fn level_select_input(
+    keyboard: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    asset_server: Res<AssetServer>,
+    progress: Res<Progress>,
+    mut select: ResMut<LevelSelectRes>,
+    mut level_assets: ResMut<LevelRes>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    let pressed = |key: KeyCode, button: GamepadButtonType| keyboard.just_pressed(key) || gamepads.iter()
+        .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(*gamepad, button)));
+    if pressed(KeyCode::Escape, GamepadButtonType::East) {
+        game_state.set(GameState::MainMenu).unwrap();
+        return;
+    }
+
+    let levels = level_assets.levels.len();
+    if levels == 0 {
+        return;
+    }
+    let mut selected = select.selected.min(levels - 1);
+    if pressed(KeyCode::Left, GamepadButtonType::DPadLeft) {
+        selected = selected.saturating_sub(1);
+    }
+    if pressed(KeyCode::Right, GamepadButtonType::DPadRight) {
+        selected = (selected + 1).min(levels - 1);
+    }
+    if pressed(KeyCode::Up, GamepadButtonType::DPadUp) && selected >= LEVEL_SELECT_COLUMNS {
+        selected -= LEVEL_SELECT_COLUMNS;
+    }
+    if pressed(KeyCode::Down, GamepadButtonType::DPadDown) && selected + LEVEL_SELECT_COLUMNS < levels {
+        selected += LEVEL_SELECT_COLUMNS;
+    }
+    select.selected = selected;
+
+    if pressed(KeyCode::Return, GamepadButtonType::South) && progress.is_unlocked(selected, &level_assets, &asset_server) {
+        level_assets.current = selected;
+        game_state.set(GameState::Loading).unwrap();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn level_select_update(
+    asset_server: Res<AssetServer>,
+    select: Res<LevelSelectRes>,
+    progress: Res<Progress>,
+    leaderboard: Res<Leaderboard>,
+    level_assets: Res<LevelRes>,
+    mut cell_query: Query<(&mut UiColor, &LevelCellComponent), Without<LevelThumbnailComponent>>,
+    mut thumbnail_query: Query<(&mut UiColor, &LevelThumbnailComponent), Without<LevelCellComponent>>,
+    mut text_query: Query<(&mut Text, &LevelCellTextComponent)>,
+) {
+    let unlocked = |level: usize| progress.is_unlocked(level, &level_assets, &asset_server);
+
+    for (mut color, cell) in cell_query.iter_mut() {
+        color.0 = if cell.level == select.selected { Color::rgba(1.0, 1.0, 1.0, 0.3) } else { Color::NONE };
+    }
+    for (mut color, thumbnail) in thumbnail_query.iter_mut() {
+        color.0 = if unlocked(thumbnail.level) { Color::WHITE } else { Color::DARK_GRAY };
+    }
+    for (mut text, cell) in text_query.iter_mut() {
+        let best = level_assets.key(cell.level, &asset_server)
+            .and_then(|key| leaderboard.entries(&key).first().map(|entry| format_time(entry.time)))
+            .unwrap_or_else(|| String::from("-"));
+        let status = if unlocked(cell.level) { format!("Best {}", best) } else { String::from("LOCKED") };
+        text.sections[0].value = format!("Level {}\n{}", cell.level + 1, status);
+    }
+}
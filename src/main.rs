@@ -11,6 +11,20 @@ mod hole;
 mod ball_anim;
 mod splash;
 mod level;
+mod level_asset;
+mod editor;
+mod maze;
+mod validate;
+mod surface;
+mod broadphase;
+mod bench;
+mod controls;
+mod settings;
+mod replay;
+mod ghost;
+mod score;
+mod menu;
+mod level_select;
 
 use arena::*;
 use ball::*;
@@ -19,8 +33,25 @@ use hole::*;
 use ball_anim::*;
 use splash::*;
 use level::*;
+use editor::*;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("validate") => std::process::exit(validate::validate_command(&args[2..])),
+        Some("bench") => std::process::exit(bench::bench_command(&args[2..])),
+        _ => {},
+    }
+
+    // A replay or a ghost file brings its own level and gravity
+    let replay = replay::replay_from_args();
+    let mut level_source = level_source_from_args();
+    let mut physics_settings = physics_settings_from_args();
+    if let Some(playback) = replay.playback.as_ref().or(replay.ghost_file.as_ref()) {
+        level_source = LevelSource::Replay;
+        physics_settings.gravity = playback.gravity;
+    }
+
     App::new()
         .insert_resource(WindowDescriptor {
             width: 800.0,
@@ -29,21 +60,93 @@ fn main() {
             resizable: false,
             ..default()
         })
-        .add_state(GameState::RespawnGrow)
+        .insert_resource(level_source)
+        .insert_resource(physics_settings)
+        .insert_resource(level_reload_settings_from_args())
+        .insert_resource(replay)
+        .insert_resource(score::run_from_args())
+        .add_state(GameState::MainMenu)
         .add_plugins(DefaultPlugins)
+        .add_plugin(controls::ControlsPlugin)
         .add_plugin(skybox::SkyboxPlugin)
+        .add_plugin(physics::PhysicsPlugin)
+        .add_plugin(broadphase::BroadphasePlugin)
         .add_plugin(ArenaPlugin)
         .add_plugin(BallPlugin)
         .add_plugin(ObstaclePlugin)
+        .add_plugin(surface::SurfacePlugin)
         .add_plugin(HolePlugin)
         .add_plugin(BallAnimPlugin)
         .add_plugin(SplashPlugin)
         .add_plugin(LevelPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(settings::SettingsPlugin)
+        .add_plugin(replay::ReplayPlugin)
+        .add_plugin(ghost::GhostPlugin)
+        .add_plugin(score::ScorePlugin)
+        .add_plugin(menu::MenuPlugin)
+        .add_plugin(level_select::LevelSelectPlugin)
         .insert_resource(Msaa {samples: 4})
         .add_startup_system(asset_server_en_hotload)
         .run();
 }
 
+// --seed <n> plays endless generated mazes, --daily plays the maze of the day,
+// --campaign <path> plays another campaign file
+fn level_source_from_args() -> LevelSource {
+    let args: Vec<String> = std::env::args().collect();
+    let mut source = LevelSource::default();
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--daily" => source = LevelSource::Daily,
+            "--seed" => {
+                i += 1;
+                match args.get(i).and_then(|seed| seed.parse().ok()) {
+                    Some(seed) => source = LevelSource::Practice(seed),
+                    None => eprintln!("--seed expects a number"),
+                }
+            },
+            "--campaign" => {
+                i += 1;
+                if let Some(path) = args.get(i) {
+                    source = LevelSource::Campaign(path.clone());
+                }
+            },
+            // Read by level_reload_settings_from_args
+            "--reload-reset-balls" => {},
+            // Read by physics_settings_from_args, replay_from_args and run_from_args
+            "--gravity" | "--record" | "--replay" | "--ghost" | "--name" => i += 1,
+            other => eprintln!("Unknown argument {}", other),
+        }
+        i += 1;
+    }
+
+    source
+}
+
+// --reload-reset-balls starts the balls over when the level file is saved
+fn level_reload_settings_from_args() -> LevelReloadSettings {
+    let keep_ball_position = !std::env::args().any(|arg| arg == "--reload-reset-balls");
+    LevelReloadSettings { keep_ball_position }
+}
+
+// --gravity <units/s²> changes how strongly the ball is pulled down the slope
+fn physics_settings_from_args() -> physics::PhysicsSettings {
+    let args: Vec<String> = std::env::args().collect();
+    let mut settings = physics::PhysicsSettings::default();
+
+    if let Some(i) = args.iter().position(|arg| arg == "--gravity") {
+        match args.get(i + 1).and_then(|gravity| gravity.parse().ok()) {
+            Some(gravity) => settings.gravity = gravity,
+            None => eprintln!("--gravity expects a number"),
+        }
+    }
+
+    settings
+}
+
 fn asset_server_en_hotload(
     asset_server: Res<AssetServer>
 ) {
@@ -52,8 +155,17 @@ fn asset_server_en_hotload(
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameState {
+    MainMenu,
+    // Grid of the campaign's levels to start from, locked until the one before is finished
+    LevelSelect,
+    Loading,
     RespawnGrow,
     Running,
-    RespawnShrink,
+    LevelComplete,
     Splash,
+    Editor,
+    // Pushed on top of Running, which is resumed when it is popped
+    Paused,
+    // Pushed on top of a menu
+    Settings,
 }
@@ -0,0 +1,276 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::level_asset::*;
+use crate::surface::Surface;
+
+pub const MAZE_CELL_SIZE: f32 = 2.0;
+pub const MAZE_WALL_THICKNESS: f32 = 0.2;
+// One in MAZE_TRAP_CHANCE cells off the solution path gets a trap hole
+const MAZE_TRAP_CHANCE: usize = 3;
+
+// SplitMix64, kept in the crate so a seed produces the same board
+// regardless of platform or dependency versions
+pub struct MazeRng {
+    state: u64,
+}
+
+impl MazeRng {
+    pub fn new(seed: u64) -> Self {
+        MazeRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform value in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+// Same seed for everyone on the same (UTC) day
+pub fn daily_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / 86400)
+        .unwrap_or(0)
+}
+
+struct Maze {
+    n: usize,
+    // east[x][y]: wall between (x, y) and (x + 1, y)
+    east: Vec<Vec<bool>>,
+    // south[x][y]: wall between (x, y) and (x, y + 1)
+    south: Vec<Vec<bool>>,
+}
+
+impl Maze {
+    // Recursive backtracker (iterative), every cell ends up reachable
+    fn carve(n: usize, rng: &mut MazeRng) -> Self {
+        let mut maze = Maze {
+            n,
+            east: vec![vec![true; n]; n],
+            south: vec![vec![true; n]; n],
+        };
+        let mut visited = vec![vec![false; n]; n];
+        let mut stack = vec![(0, 0)];
+        visited[0][0] = true;
+
+        while let Some(&(x, y)) = stack.last() {
+            let neighbours: Vec<(usize, usize)> = maze.neighbours(x, y)
+                .into_iter()
+                .filter(|&(nx, ny)| !visited[nx][ny])
+                .collect();
+
+            if neighbours.is_empty() {
+                stack.pop();
+                continue;
+            }
+
+            let (nx, ny) = neighbours[rng.below(neighbours.len())];
+            maze.set_wall((x, y), (nx, ny), false);
+            visited[nx][ny] = true;
+            stack.push((nx, ny));
+        }
+
+        maze
+    }
+
+    fn neighbours(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let mut result = Vec::with_capacity(4);
+        if x > 0 { result.push((x - 1, y)); }
+        if y > 0 { result.push((x, y - 1)); }
+        if x + 1 < self.n { result.push((x + 1, y)); }
+        if y + 1 < self.n { result.push((x, y + 1)); }
+        result
+    }
+
+    fn set_wall(&mut self, a: (usize, usize), b: (usize, usize), wall: bool) {
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        if low.0 != high.0 {
+            self.east[low.0][low.1] = wall;
+        } else {
+            self.south[low.0][low.1] = wall;
+        }
+    }
+
+    fn is_open(&self, a: (usize, usize), b: (usize, usize)) -> bool {
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        if low.0 != high.0 {
+            !self.east[low.0][low.1]
+        } else {
+            !self.south[low.0][low.1]
+        }
+    }
+
+    // Breadth first search from `start`, returns the distance and parent of every cell
+    fn distances(&self, start: (usize, usize)) -> (Vec<Vec<usize>>, Vec<Vec<Option<(usize, usize)>>>) {
+        let mut distance = vec![vec![usize::MAX; self.n]; self.n];
+        let mut parent = vec![vec![None; self.n]; self.n];
+        let mut queue = std::collections::VecDeque::new();
+        distance[start.0][start.1] = 0;
+        queue.push_back(start);
+
+        while let Some((x, y)) = queue.pop_front() {
+            for (nx, ny) in self.neighbours(x, y) {
+                if distance[nx][ny] == usize::MAX && self.is_open((x, y), (nx, ny)) {
+                    distance[nx][ny] = distance[x][y] + 1;
+                    parent[nx][ny] = Some((x, y));
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+
+        (distance, parent)
+    }
+}
+
+// Builds a maze board for the given seed, walls become box obstacles,
+// the goal is placed in the cell furthest from the start and traps are
+// only placed off the solution path so the goal is always reachable
+pub fn generate_maze(seed: u64, arena_size: f32) -> LevelAsset {
+    let mut rng = MazeRng::new(seed);
+    let n = ((arena_size / MAZE_CELL_SIZE).floor() as usize).max(2);
+    let offset = -(n as f32) * MAZE_CELL_SIZE / 2.0;
+    let cell_center = |i: usize| offset + (i as f32 + 0.5) * MAZE_CELL_SIZE;
+
+    let maze = Maze::carve(n, &mut rng);
+
+    let start = (0, 0);
+    let (distance, parent) = maze.distances(start);
+    let mut goal = start;
+    for x in 0..n {
+        for y in 0..n {
+            if distance[x][y] > distance[goal.0][goal.1] {
+                goal = (x, y);
+            }
+        }
+    }
+
+    let mut on_path = vec![vec![false; n]; n];
+    let mut cell = Some(goal);
+    while let Some((x, y)) = cell {
+        on_path[x][y] = true;
+        cell = parent[x][y];
+    }
+
+    let mut holes = vec![HoleDef {
+        position: (cell_center(goal.0), cell_center(goal.1)),
+        is_final: true,
+    }];
+    for x in 0..n {
+        for y in 0..n {
+            if !on_path[x][y] && rng.below(MAZE_TRAP_CHANCE) == 0 {
+                holes.push(HoleDef {
+                    position: (cell_center(x), cell_center(y)),
+                    is_final: false,
+                });
+            }
+        }
+    }
+
+    LevelAsset {
+        arena_size,
+        ball_start: (cell_center(start.0), cell_center(start.1)),
+        extra_balls: Vec::new(),
+        balls_to_win: None,
+        holes,
+        obstacles: wall_obstacles(&maze, offset),
+        floor: Surface::Wood,
+        zones: Vec::new(),
+    }
+}
+
+// Merges neighbouring wall edges on the same line into a single obstacle
+fn wall_obstacles(maze: &Maze, offset: f32) -> Vec<ObstacleDef> {
+    let n = maze.n;
+    let mut obstacles = Vec::new();
+    let mut push_run = |horizontal: bool, line: usize, from: usize, to: usize| {
+        let along = offset + (from + to) as f32 / 2.0 * MAZE_CELL_SIZE;
+        let across = offset + line as f32 * MAZE_CELL_SIZE;
+        let length = (to - from) as f32 * MAZE_CELL_SIZE + MAZE_WALL_THICKNESS;
+        obstacles.push(if horizontal {
+            ObstacleDef { position: (along, across), size: (length, MAZE_WALL_THICKNESS), rotation: 0.0, colider: None, material: Surface::Wood, motion: None }
+        } else {
+            ObstacleDef { position: (across, along), size: (MAZE_WALL_THICKNESS, length), rotation: 0.0, colider: None, material: Surface::Wood, motion: None }
+        });
+    };
+
+    // Walls along x at z line `line` (between rows line - 1 and line)
+    for line in 0..=n {
+        let mut run_start = None;
+        for x in 0..=n {
+            let wall = x < n && (line == 0 || line == n || maze.south[x][line - 1]);
+            match (wall, run_start) {
+                (true, None) => run_start = Some(x),
+                (false, Some(from)) => {
+                    push_run(true, line, from, x);
+                    run_start = None;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    // Walls along z at x line `line` (between columns line - 1 and line)
+    for line in 0..=n {
+        let mut run_start = None;
+        for y in 0..=n {
+            let wall = y < n && (line == 0 || line == n || maze.east[line - 1][y]);
+            match (wall, run_start) {
+                (true, None) => run_start = Some(y),
+                (false, Some(from)) => {
+                    push_run(false, line, from, y);
+                    run_start = None;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    obstacles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::validate_level;
+
+    // LevelAsset has no PartialEq, the debug output lists every field
+    fn layout(level: &LevelAsset) -> String {
+        format!("{:?}", level)
+    }
+
+    #[test]
+    fn same_seed_same_maze() {
+        for seed in [0, 1, 1234, u64::MAX] {
+            assert_eq!(layout(&generate_maze(seed, 12.0)), layout(&generate_maze(seed, 12.0)));
+        }
+        assert_ne!(layout(&generate_maze(1, 12.0)), layout(&generate_maze(2, 12.0)));
+    }
+
+    #[test]
+    fn final_hole_is_reachable() {
+        for seed in 0..20 {
+            let level = generate_maze(seed, 12.0);
+            assert_eq!(level.holes.iter().filter(|hole| hole.is_final).count(), 1);
+            // Also checks that the ball rolls to the goal without crossing a trap
+            if let Err(errors) = validate_level(&level) {
+                panic!("maze {} is invalid: {:?}", seed, errors);
+            }
+        }
+    }
+
+    #[test]
+    fn larger_arenas_are_reachable_too() {
+        for seed in 0..3 {
+            let level = generate_maze(seed, 20.0);
+            assert!(validate_level(&level).is_ok(), "maze {} in a 20 unit arena is invalid", seed);
+        }
+    }
+}
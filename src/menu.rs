@@ -0,0 +1,205 @@
+use bevy::prelude::*;
+use bevy::app::AppExit;
+
+use crate::controls::*;
+use crate::level::LevelRes;
+
+use super::GameState;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<MenuRes>()
+            .add_system_set(SystemSet::on_update(GameState::Running).with_system(pause_open))
+            // The key that closed the pause menu would open it again
+            .add_system_set(SystemSet::on_resume(GameState::Running).with_system(input_consume));
+
+        // Settings is pushed on top of a menu, which comes back when it is popped
+        for state in [GameState::MainMenu, GameState::Paused] {
+            app
+                .add_system_set(SystemSet::on_enter(state.clone()).with_system(menu_enter).with_system(input_consume))
+                .add_system_set(SystemSet::on_resume(state.clone()).with_system(menu_enter).with_system(input_consume))
+                .add_system_set(SystemSet::on_update(state.clone()).with_system(menu_input))
+                .add_system_set(SystemSet::on_update(state.clone()).with_system(menu_text_update))
+                .add_system_set(SystemSet::on_pause(state.clone()).with_system(menu_exit))
+                .add_system_set(SystemSet::on_exit(state).with_system(menu_exit));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuItem {
+    Play,
+    LevelSelect,
+    Resume,
+    Restart,
+    Settings,
+    MainMenu,
+    Quit,
+}
+
+impl MenuItem {
+    pub fn name(&self) -> String {
+        match self {
+            MenuItem::Play => String::from("Play"),
+            MenuItem::LevelSelect => String::from("Select level"),
+            MenuItem::Resume => String::from("Resume"),
+            MenuItem::Restart => String::from("Restart level"),
+            MenuItem::Settings => String::from("Settings"),
+            MenuItem::MainMenu => String::from("Back to menu"),
+            MenuItem::Quit => String::from("Quit"),
+        }
+    }
+}
+
+// Selected item of the open menu
+#[derive(Default)]
+pub struct MenuRes {
+    selected: usize,
+}
+
+#[derive(Component)]
+struct MenuTextComponent;
+
+// Title, line below it and items of the menu shown in `state`
+fn menu_items(state: &GameState) -> (&'static str, &'static str, Vec<MenuItem>) {
+    match state {
+        GameState::MainMenu => (
+            "ROLLING BALL",
+            "Tilt the board and roll the ball into the golden hole",
+            vec![MenuItem::Play, MenuItem::LevelSelect, MenuItem::Settings, MenuItem::Quit],
+        ),
+        GameState::Paused => (
+            "PAUSED",
+            "",
+            vec![MenuItem::Resume, MenuItem::Restart, MenuItem::Settings, MenuItem::MainMenu, MenuItem::Quit],
+        ),
+        _ => ("", "", Vec::new()),
+    }
+}
+
+// Playing stops underneath: physics, tilt and ball animations only run in
+// Running and RespawnGrow, which stay on the stack until the menu is closed
+fn pause_open(
+    actions: Res<ActionState>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    if actions.just_pressed(Action::Pause) {
+        // A level finished on the same frame moves on instead
+        game_state.push(GameState::Paused).ok();
+    }
+}
+
+fn menu_enter(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut menu: ResMut<MenuRes>,
+) {
+    *menu = MenuRes::default();
+
+    let font = asset_server.load("fonts/arial.ttf");
+    commands
+        .spawn_bundle(
+            TextBundle::from_sections([
+                TextSection::new("", TextStyle { font: font.clone(), font_size: 60.0, color: Color::WHITE }),
+                TextSection::new("", TextStyle { font: font.clone(), font_size: 20.0, color: Color::GRAY }),
+                TextSection::new("", TextStyle { font, font_size: 30.0, color: Color::WHITE }),
+            ])
+            .with_style(Style {
+                position_type: PositionType::Relative,
+                margin: UiRect {
+                    top: Val::Auto,
+                    left: Val::Auto,
+                    right: Val::Auto,
+                    bottom: Val::Auto,
+                },
+                align_self: AlignSelf::Center,
+                ..default()
+            }))
+        .insert(MenuTextComponent);
+}
+
+fn menu_exit(
+    mut commands: Commands,
+    text_query: Query<Entity, With<MenuTextComponent>>,
+) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Up/Down and Enter or the gamepad's d-pad and south button pick an item,
+// Esc, the east button or the pause action close the pause menu
+fn menu_input(
+    keyboard: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    actions: Res<ActionState>,
+    mut menu: ResMut<MenuRes>,
+    mut game_state: ResMut<State<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let pad = |button: GamepadButtonType| gamepads.iter()
+        .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(*gamepad, button)));
+    let state = game_state.current().clone();
+    let (_, _, items) = menu_items(&state);
+    if items.is_empty() {
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Up) || pad(GamepadButtonType::DPadUp) {
+        menu.selected = (menu.selected + items.len() - 1) % items.len();
+    }
+    if keyboard.just_pressed(KeyCode::Down) || pad(GamepadButtonType::DPadDown) {
+        menu.selected = (menu.selected + 1) % items.len();
+    }
+    menu.selected = menu.selected.min(items.len() - 1);
+
+    let back = keyboard.just_pressed(KeyCode::Escape) || pad(GamepadButtonType::East) || actions.just_pressed(Action::Pause);
+    let item = if keyboard.just_pressed(KeyCode::Return) || pad(GamepadButtonType::South) {
+        items[menu.selected]
+    } else if back && state == GameState::Paused {
+        MenuItem::Resume
+    } else {
+        return;
+    };
+
+    match item {
+        MenuItem::Play => game_state.set(GameState::Loading).unwrap(),
+        MenuItem::LevelSelect => game_state.set(GameState::LevelSelect).unwrap(),
+        MenuItem::Resume => game_state.pop().unwrap(),
+        // Leaving the level for Loading or the menu tears the arena down
+        MenuItem::Restart => game_state.replace(GameState::Loading).unwrap(),
+        MenuItem::Settings => game_state.push(GameState::Settings).unwrap(),
+        MenuItem::MainMenu => game_state.replace(GameState::MainMenu).unwrap(),
+        MenuItem::Quit => exit.send(AppExit),
+    }
+}
+
+fn menu_text_update(
+    game_state: Res<State<GameState>>,
+    menu: Res<MenuRes>,
+    level_assets: Res<LevelRes>,
+    mut text_query: Query<&mut Text, With<MenuTextComponent>>,
+) {
+    let (title, hint, items) = menu_items(game_state.current());
+    let lines: Vec<String> = items.iter()
+        .enumerate()
+        .map(|(i, item)| format!("{} {}", if i == menu.selected { ">" } else { " " }, item.name()))
+        .collect();
+
+    // A level that failed to load replaces the line below the title
+    let (hint, hint_color) = match (game_state.current(), &level_assets.load_error) {
+        (GameState::MainMenu, Some(error)) => (error.as_str(), Color::RED),
+        _ => (hint, Color::GRAY),
+    };
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!("{}\n", title);
+        text.sections[1].value = format!("{}\n\n", hint);
+        text.sections[1].style.color = hint_color;
+        text.sections[2].value = lines.join("\n");
+    }
+}
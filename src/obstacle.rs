@@ -1,76 +1,678 @@
 use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use serde::{Deserialize, Serialize};
 use crate::physics::*;
-use crate::ball::*;
+use crate::ball::BALL_RADIUS;
 
 use super::GameState;
 
-const OBSTACLE_BOUNCE: f32 = 0.2;
+#[derive(Component)]
+pub struct ObstacleComponent {
+    // Place in the level file, collisions are resolved in this order
+    pub index: usize,
+}
+
+// How an obstacle moves, relative to where the level file places it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ObstacleMotion {
+    // Slides and turns through the keyframes, starts over from the first one
+    // after the last or runs them backwards with `ping_pong`
+    Path {
+        keys: Vec<Keyframe>,
+        #[serde(default)]
+        ping_pong: bool,
+    },
+    // Spins about `pivot` (offset from the obstacle position) at `speed`
+    // degrees per second, sweepers turn about one of their ends
+    Rotate {
+        #[serde(default)]
+        pivot: (f32, f32),
+        speed: f32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    // Seconds since the start of the path
+    pub time: f32,
+    // (x, z) offset from the obstacle position
+    #[serde(default)]
+    pub offset: (f32, f32),
+    // Degrees added to the obstacle rotation
+    #[serde(default)]
+    pub rotation: f32,
+}
 
+impl Keyframe {
+    fn offset(&self) -> Vec2 {
+        Vec2::new(self.offset.0, self.offset.1)
+    }
+}
+
+impl ObstacleMotion {
+    // Offset from the placed position and extra rotation (radians) at `time`,
+    // along with the speed of the obstacle origin and its turn rate
+    pub fn sample(&self, time: f32) -> (Vec2, f32, Vec2, f32) {
+        match self {
+            ObstacleMotion::Rotate { pivot, speed } => {
+                let pivot = Vec2::new(pivot.0, pivot.1);
+                let angular_speed = speed.to_radians();
+                let angle = angular_speed * time;
+                let arm = Quat::from_rotation_y(angle) * Vec3::new(-pivot.x, 0.0, -pivot.y);
+                let arm = Vec2::new(arm.x, arm.z);
+                (pivot + arm, angle, angular_speed * Vec2::new(arm.y, -arm.x), angular_speed)
+            },
+            ObstacleMotion::Path { keys, ping_pong } => {
+                let (first, last) = match (keys.first(), keys.last()) {
+                    (Some(first), Some(last)) => (first, last),
+                    _ => return (Vec2::ZERO, 0.0, Vec2::ZERO, 0.0),
+                };
+                let period = last.time - first.time;
+                if period <= 0.0 {
+                    return (first.offset(), first.rotation.to_radians(), Vec2::ZERO, 0.0);
+                }
+
+                let mut t = time.rem_euclid(if *ping_pong { 2.0 * period } else { period });
+                let mut direction = 1.0;
+                if t > period {
+                    t = 2.0 * period - t;
+                    direction = -1.0;
+                }
+                let t = first.time + t;
+
+                let i = keys.windows(2).position(|pair| t < pair[1].time).unwrap_or(keys.len() - 2);
+                let (a, b) = (&keys[i], &keys[i + 1]);
+                let duration = b.time - a.time;
+                if duration <= 0.0 {
+                    return (b.offset(), b.rotation.to_radians(), Vec2::ZERO, 0.0);
+                }
+                let progress = ((t - a.time) / duration).clamp(0.0, 1.0);
+                let turn = (b.rotation - a.rotation).to_radians();
+                (
+                    a.offset().lerp(b.offset(), progress),
+                    a.rotation.to_radians() + turn * progress,
+                    direction * (b.offset() - a.offset()) / duration,
+                    direction * turn / duration,
+                )
+            },
+        }
+    }
+}
+
+// Moves the obstacle it is attached to, stepped with the physics
 #[derive(Component)]
-pub struct ObstacleComponent;
+pub struct ObstacleMover {
+    pub motion: ObstacleMotion,
+    // Position and rotation (radians) from the level file
+    pub origin: Vec2,
+    pub rotation: f32,
+    // Seconds the obstacle has been moving
+    pub time: f32,
+    // Fastest turn (radians per second) the physics steps can keep up with
+    pub max_angular_speed: f32,
+}
+
+impl ObstacleMover {
+    pub fn new(motion: ObstacleMotion, origin: Vec2, rotation: f32, colider: &Colider) -> Self {
+        // Balls are only tested against where the obstacle is after each step,
+        // its farthest point may move a ball radius per step without passing through one
+        let pivot = match &motion {
+            ObstacleMotion::Rotate { pivot, .. } => Vec2::new(pivot.0, pivot.1).length(),
+            ObstacleMotion::Path { .. } => 0.0,
+        };
+        let reach = (colider_bounds(colider) + pivot).max(f32::EPSILON);
+        let max_angular_speed = BALL_RADIUS / (reach * PHYSICS_TIMESTEP);
+        ObstacleMover { motion, origin, rotation, time: 0.0, max_angular_speed }
+    }
+}
 
 #[derive(Bundle)]
 pub struct ObstacleBundle {
     pub obstacle_comp: ObstacleComponent,
     pub po: PhysicsObject,
+    pub material: PhysicsMaterial,
     #[bundle]
     pub pbr: PbrBundle,
 }
 
+pub struct ObstacleRes {
+    pub mesh: Handle<Mesh>,
+}
+
+impl FromWorld for ObstacleRes {
+    fn from_world(world: &mut World) -> Self {
+        // Unit box standing on the arena, scaled per obstacle
+        let mesh_handle = world.resource_mut::<Assets<Mesh>>().add(
+            Mesh::from(shape::Box{
+                max_x: 0.5,
+                max_y: 1.0,
+                max_z: 0.5,
+                min_x:-0.5,
+                min_y: 0.0,
+                min_z:-0.5,
+            }));
+
+        ObstacleRes { mesh: mesh_handle }
+    }
+}
+
+const OBSTACLE_ROUND_SEGMENTS: usize = 24;
+
+// Outline the obstacle mesh is extruded from, rounded parts are approximated
+pub fn colider_outline(colider: &Colider) -> Vec<Vec2> {
+    let arc = |center: Vec2, r: f32, from: f32, segments: usize| -> Vec<Vec2> {
+        (0..=segments)
+            .map(|i| from + std::f32::consts::PI * i as f32 / (OBSTACLE_ROUND_SEGMENTS / 2) as f32)
+            .map(|angle| center + r * Vec2::new(angle.cos(), angle.sin()))
+            .collect()
+    };
+
+    match colider {
+        Colider::CircleColider(r) => {
+            let mut outline = arc(Vec2::ZERO, *r, 0.0, OBSTACLE_ROUND_SEGMENTS);
+            outline.pop();
+            outline
+        },
+        Colider::CapsuleColider(a, b, r) => {
+            let (a, b) = (Vec2::new(a.0, a.1), Vec2::new(b.0, b.1));
+            let direction = (b - a).normalize_or_zero();
+            let start = direction.y.atan2(direction.x) - std::f32::consts::FRAC_PI_2;
+            let mut outline = arc(b, *r, start, OBSTACLE_ROUND_SEGMENTS / 2);
+            outline.extend(arc(a, *r, start + std::f32::consts::PI, OBSTACLE_ROUND_SEGMENTS / 2));
+            outline
+        },
+        colider => colider_shape(colider).map(|(vertices, _)| vertices).unwrap_or_default(),
+    }
+}
+
+// Boxes reuse the scaled unit box, every other colider gets its outline extruded
+pub fn obstacle_mesh(colider: &Colider, height: f32) -> Option<Mesh> {
+    if let Colider::BoxColider(_, _) = colider {
+        return None;
+    }
+    Some(prism_mesh(&colider_outline(colider), height))
+}
+
+// Extrudes a counter-clockwise (x, z) outline up to `height`, top and sides only
+pub fn prism_mesh(outline: &[Vec2], height: f32) -> Mesh {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let n = outline.len();
+
+    // top
+    for point in outline {
+        positions.push([point.x, height, point.y]);
+        normals.push([0.0, 1.0, 0.0]);
+        uvs.push([point.x, point.y]);
+    }
+    for i in 1..n.saturating_sub(1) {
+        indices.extend_from_slice(&[0, (i + 1) as u32, i as u32]);
+    }
+
+    // sides, separate vertices per face for flat normals
+    let mut along = 0.0;
+    for i in 0..n {
+        let (a, b) = (outline[i], outline[(i + 1) % n]);
+        let edge = b - a;
+        let normal = Vec2::new(edge.y, -edge.x).normalize_or_zero();
+        let base = positions.len() as u32;
+        for (point, y, u) in [(a, 0.0, along), (b, 0.0, along + edge.length()), (b, height, along + edge.length()), (a, height, along)] {
+            positions.push([point.x, y, point.y]);
+            normals.push([normal.x, 0.0, normal.y]);
+            uvs.push([u, y]);
+        }
+        indices.extend_from_slice(&[base, base + 3, base + 2, base, base + 2, base + 1]);
+        along += edge.length();
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
 pub struct ObstaclePlugin;
 
 impl Plugin for ObstaclePlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_system_set(SystemSet::on_update(GameState::Running).with_system(obstacle_system));
+            .init_resource::<ObstacleRes>()
+            .add_system_set(SystemSet::on_enter(GameState::RespawnGrow).with_system(obstacle_motion_reset));
     }
 }
 
-fn obstacle_system(
-    mut ball_query: Query<(&mut Transform, &mut PhysicsObject, &BallComponent)>,
-    mut obstacle_query: Query<(&mut Transform, &mut PhysicsObject, &ObstacleComponent, Without<BallComponent>)>,
+// Moving obstacles start over together with the balls
+fn obstacle_motion_reset(
+    mut mover_query: Query<(&mut Transform, &mut PhysicsObject, &mut ObstacleMover)>,
 ) {
-    let mut ball_iterator = ball_query.iter_mut();
-    // Grab all ball entitys
-    while let Some(mut ball) = ball_iterator.next()  {
-        let mut other_iter = obstacle_query.iter_mut();
-        // Grab other coliders
-        while let Some(mut other) = other_iter.next()  {
-            resolve_colission(ball.0.as_mut(), ball.1.as_mut(), other.0.as_mut(), other.1.as_mut());
-        }
+    for (mut transform, mut po, mut mover) in mover_query.iter_mut() {
+        mover.time = 0.0;
+        obstacle_move(&mut transform, &mut po, &mut mover, 0.0);
     }
 }
 
-fn resolve_colission(tr_a: &mut Transform, po_a: &mut PhysicsObject, tr_b: &mut Transform, po_b: &mut PhysicsObject) {
-    if let Colider::CircleColider(ball_r) = po_a.colider {
-        match po_b.colider {
-            Colider::BoxColider(box_w, box_h) => {
-                // BALL vs BOX 
-                let diff = Vec2::new(
-                    (-box_w/2.0).max((box_w/2.0).min(tr_a.translation.x - tr_b.translation.x)),
-                    (-box_h/2.0).max((box_h/2.0).min(tr_a.translation.z - tr_b.translation.z))
-                );
-                let u = Vec2::new(tr_a.translation.x, tr_a.translation.z) - Vec2::new(tr_b.translation.x, tr_b.translation.z) - diff;
-                if u.length() < ball_r {
-                    let norm = u.normalize();
-                    //println!("{:?} {:?}", u, norm);
-                    let res = 2.0 * (ball_r - u.length()) * norm;
-                    
-                    tr_a.translation.x += res.x;
-                    tr_a.translation.z += res.y;
-
-                    po_a.speed = po_a.speed + (1.0 + OBSTACLE_BOUNCE) * (po_a.speed.dot(-norm)) * norm;
+// Advances a moving obstacle by one physics step. Its speed is kept in the
+// PhysicsObject so contacts can hand it on to the ball. Turns faster than
+// `max_angular_speed` slow the whole motion down instead.
+pub fn obstacle_move(
+    transform: &mut Transform,
+    po: &mut PhysicsObject,
+    mover: &mut ObstacleMover,
+    dt: f32,
+) {
+    let (_, _, _, angular_speed) = mover.motion.sample(mover.time);
+    let pace = (mover.max_angular_speed / angular_speed.abs()).min(1.0);
+    mover.time += dt * pace;
+    let (offset, angle, speed, angular_speed) = mover.motion.sample(mover.time);
+    let position = mover.origin + offset;
+    transform.translation.x = position.x;
+    transform.translation.z = position.y;
+    transform.rotation = Quat::from_rotation_y(mover.rotation + angle);
+    po.speed = speed * pace;
+    po.angular_speed = angular_speed * pace;
+}
+
+// Most contacts handled within one step, remaining motion is dropped after that
+const OBSTACLE_MAX_CONTACTS: u32 = 4;
+// Distance kept from a wall after moving the ball to the contact point
+const OBSTACLE_CONTACT_OFFSET: f32 = 0.001;
+
+// Moves the ball by its speed over `dt`, stopping at the first obstacle in
+// the way, bouncing and continuing with the rest of the motion.
+// Overlaps that are already there get pushed out afterwards.
+pub fn obstacle_step(
+    ball_po: &mut PhysicsObject,
+    obstacles: &[(&Transform, &PhysicsObject, &PhysicsMaterial)],
+    dt: f32,
+) {
+    let mut remaining = 1.0;
+    for _ in 0..OBSTACLE_MAX_CONTACTS {
+        let motion = ball_po.speed * dt * remaining;
+        if motion == Vec2::ZERO {
+            break;
+        }
+
+        let mut first_hit: Option<(f32, Vec2, usize)> = None;
+        for (i, &(obstacle_transform, obstacle_po, _)) in obstacles.iter().enumerate() {
+            if let Some((t, normal)) = sweep_colission(ball_po, motion, obstacle_transform, obstacle_po) {
+                // A wall moving away faster than the ball is never caught up with
+                let surface = surface_velocity(ball_po.position + motion * t, obstacle_transform, obstacle_po);
+                if (ball_po.speed - surface).dot(normal) >= 0.0 {
+                    continue;
                 }
+                if first_hit.map_or(true, |(first_t, ..)| t < first_t) {
+                    first_hit = Some((t, normal, i));
+                }
+            }
+        }
+
+        match first_hit {
+            Some((t, normal, i)) => {
+                let (obstacle_transform, obstacle_po, material) = obstacles[i];
+                ball_po.position += motion * t + normal * OBSTACLE_CONTACT_OFFSET;
+                let surface = surface_velocity(ball_po.position, obstacle_transform, obstacle_po);
+                bounce(ball_po, normal, material, surface);
+                remaining *= 1.0 - t;
             },
-            Colider::CircleColider(_hole_r) => {
-                todo!();
-                // BALL vs HOLE
-                // let center_distance = ((tr_a.translation.x - tr_b.translation.x).powi(2) + (tr_a.translation.z - tr_b.translation.z).powi(2)).sqrt();
-                // if center_distance < ball_r + hole_r {
-                //     // hole collision
-                // }
+            None => {
+                ball_po.position += motion;
+                remaining = 0.0;
+                break;
             },
         }
+    }
+    if remaining > 0.0 {
+        // Stuck between walls, stay at the last contact
+        ball_po.speed = Vec2::ZERO;
+    }
+
+    obstacle_push_out(ball_po, obstacles);
+}
+
+// Pushes the ball back out of any obstacle it ended up in, also used after
+// other balls pushed it so that walls always win over the balls
+pub fn obstacle_push_out(
+    ball_po: &mut PhysicsObject,
+    obstacles: &[(&Transform, &PhysicsObject, &PhysicsMaterial)],
+) {
+    for &(obstacle_transform, obstacle_po, material) in obstacles {
+        resolve_colission(ball_po, obstacle_transform, obstacle_po, material);
+    }
+}
+
+// Reflects the speed into the wall scaled by the restitution, friction
+// takes away sliding speed in proportion to how hard the ball hit.
+// Works on the speed relative to the wall surface, so a moving wall
+// carries the ball along.
+fn bounce(po: &mut PhysicsObject, norm: Vec2, material: &PhysicsMaterial, surface: Vec2) {
+    let relative = po.speed - surface;
+    let normal_speed = relative.dot(norm);
+    if normal_speed >= 0.0 {
+        return;
+    }
+    let tangent = relative - normal_speed * norm;
+    let friction_loss = (material.friction * (1.0 + material.restitution) * normal_speed.abs()).min(tangent.length());
+    po.speed = surface + tangent - tangent.normalize_or_zero() * friction_loss - material.restitution * normal_speed * norm;
+}
+
+// Speed of the obstacle surface where the ball touches it, zero for static ones
+fn surface_velocity(point: Vec2, tr: &Transform, po: &PhysicsObject) -> Vec2 {
+    po.velocity_at(point - Vec2::new(tr.translation.x, tr.translation.z))
+}
+
+// Time of impact (0..1 along `motion`) and contact normal of the ball
+// moving into the obstacle, None if it misses or already overlaps
+fn sweep_colission(po_a: &PhysicsObject, motion: Vec2, tr_b: &Transform, po_b: &PhysicsObject) -> Option<(f32, Vec2)> {
+    if let Colider::CircleColider(ball_r) = po_a.colider {
+        let (vertices, rounding) = colider_shape(&po_b.colider)?;
+        // Work in obstacle space so the obstacle rotation is honoured
+        let start = to_local(tr_b, po_a.position - Vec2::new(tr_b.translation.x, tr_b.translation.z));
+        let local_motion = to_local(tr_b, motion);
+        let (t, normal) = sweep_circle_convex(start, local_motion, ball_r + rounding, &vertices)?;
+        let normal = to_world(tr_b, normal);
+        if motion.dot(normal) < 0.0 {
+            return Some((t, normal));
+        }
+    }
+    None
+}
+
+// Obstacle space is the (x, z) plane of the obstacle rotated about Y
+fn to_local(tr: &Transform, v: Vec2) -> Vec2 {
+    let local = tr.rotation.inverse() * Vec3::new(v.x, 0.0, v.y);
+    Vec2::new(local.x, local.z)
+}
+
+fn to_world(tr: &Transform, v: Vec2) -> Vec2 {
+    let world = tr.rotation * Vec3::new(v.x, 0.0, v.y);
+    Vec2::new(world.x, world.z)
+}
+
+// Counter-clockwise convex outline of the colider in obstacle space and
+// the radius the outline is rounded by (capsules are a rounded segment,
+// circles a rounded point)
+pub fn colider_shape(colider: &Colider) -> Option<(Vec<Vec2>, f32)> {
+    match colider {
+        Colider::BoxColider(w, h) => Some((vec![
+            Vec2::new(-w / 2.0, -h / 2.0),
+            Vec2::new( w / 2.0, -h / 2.0),
+            Vec2::new( w / 2.0,  h / 2.0),
+            Vec2::new(-w / 2.0,  h / 2.0),
+        ], 0.0)),
+        Colider::PolygonColider(points) => {
+            let mut vertices: Vec<Vec2> = points.iter().map(|&(x, z)| Vec2::new(x, z)).collect();
+            let area: f32 = (0..vertices.len())
+                .map(|i| vertices[i].perp_dot(vertices[(i + 1) % vertices.len()]))
+                .sum();
+            if area < 0.0 {
+                vertices.reverse();
+            }
+            Some((vertices, 0.0))
+        },
+        Colider::CapsuleColider(a, b, r) => Some((vec![Vec2::new(a.0, a.1), Vec2::new(b.0, b.1)], *r)),
+        Colider::CircleColider(r) => Some((vec![Vec2::ZERO], *r)),
+    }
+}
+
+// Distance from the obstacle origin to its farthest point, whatever the rotation
+pub fn colider_bounds(colider: &Colider) -> f32 {
+    colider_shape(colider).map_or(0.0, |(vertices, rounding)| {
+        vertices.iter().map(|vertex| vertex.length()).fold(0.0, f32::max) + rounding
+    })
+}
+
+// Area around the ball that obstacle_step can reach within one step,
+// bounces never make it faster than twice its speed
+pub fn obstacle_step_reach(ball_po: &PhysicsObject, dt: f32) -> f32 {
+    colider_bounds(&ball_po.colider) + 2.0 * ball_po.speed.length() * dt + OBSTACLE_CONTACT_OFFSET
+}
+
+// Outward normal of the edge from `a` to `b` of a counter-clockwise outline
+fn edge_normal(a: Vec2, b: Vec2) -> Vec2 {
+    let edge = b - a;
+    Vec2::new(edge.y, -edge.x).normalize_or_zero()
+}
+
+fn closest_point_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let edge = b - a;
+    let length_squared = edge.length_squared();
+    if length_squared < f32::EPSILON {
+        return a;
+    }
+    a + edge * ((point - a).dot(edge) / length_squared).clamp(0.0, 1.0)
+}
+
+// Circle of radius `r` moving from `start` by `motion` against a convex outline.
+// Same as a ray against the outline grown by `r`: shifted edges and round corners.
+fn sweep_circle_convex(start: Vec2, motion: Vec2, r: f32, vertices: &[Vec2]) -> Option<(f32, Vec2)> {
+    let mut first_hit: Option<(f32, Vec2)> = None;
+    let mut consider = |t: f32, normal: Vec2| {
+        if first_hit.map_or(true, |(first_t, _)| t < first_t) {
+            first_hit = Some((t, normal));
+        }
+    };
+
+    let n = vertices.len();
+    if n >= 2 {
+        for i in 0..n {
+            let (a, b) = (vertices[i], vertices[(i + 1) % n]);
+            let normal = edge_normal(a, b);
+            let approach = normal.dot(motion);
+            let distance = normal.dot(start - a);
+            // Moving away, parallel, or already past the grown edge
+            if approach >= 0.0 || normal == Vec2::ZERO || distance < r {
+                continue;
+            }
+            let t = (r - distance) / approach;
+            if t > 1.0 {
+                continue;
+            }
+            let along = (start + motion * t - a).dot(b - a) / (b - a).length_squared();
+            if (0.0..=1.0).contains(&along) {
+                consider(t, normal);
+            }
+        }
+    }
+
+    for &vertex in vertices {
+        if let Some(t) = sweep_circle_point(start, motion, r, vertex) {
+            consider(t, (start + motion * t - vertex) / r);
+        }
+    }
+
+    first_hit
+}
+
+// Time of impact of a circle of radius `r` with a point, ray vs circle
+fn sweep_circle_point(start: Vec2, motion: Vec2, r: f32, point: Vec2) -> Option<f32> {
+    let f = start - point;
+    let a = motion.dot(motion);
+    let b = f.dot(motion);
+    let c = f.dot(f) - r * r;
+    let discriminant = b * b - a * c;
+    if c < 0.0 || discriminant < 0.0 || a < f32::EPSILON {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / a;
+    if (0.0..=1.0).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// Depth and direction to push a circle at `point` out of the convex outline
+fn penetration_convex(point: Vec2, r: f32, vertices: &[Vec2]) -> Option<(f32, Vec2)> {
+    let n = vertices.len();
+    if n >= 3 {
+        // Inside the outline, leave through the nearest edge
+        let nearest_edge = (0..n)
+            .map(|i| {
+                let normal = edge_normal(vertices[i], vertices[(i + 1) % n]);
+                (normal.dot(point - vertices[i]), normal)
+            })
+            .fold(None, |best: Option<(f32, Vec2)>, edge| match best {
+                Some(best) if best.0 >= edge.0 => Some(best),
+                _ => Some(edge),
+            });
+        if let Some((distance, normal)) = nearest_edge {
+            if distance <= 0.0 {
+                return Some((r - distance, normal));
+            }
+        }
+    }
+
+    let closest = match n {
+        0 => return None,
+        1 => vertices[0],
+        _ => (0..n)
+            .map(|i| closest_point_on_segment(point, vertices[i], vertices[(i + 1) % n]))
+            .fold(vertices[0], |best, candidate| {
+                if point.distance_squared(candidate) < point.distance_squared(best) { candidate } else { best }
+            }),
+    };
+
+    let diff = point - closest;
+    let distance = diff.length();
+    if distance >= r {
+        return None;
+    }
+    let normal = if distance > f32::EPSILON {
+        diff / distance
+    } else if n >= 2 {
+        edge_normal(vertices[0], vertices[1])
+    } else {
+        Vec2::ZERO
+    };
+    // Dead center of a circle or a degenerate outline, any way out will do
+    let normal = if normal == Vec2::ZERO { Vec2::X } else { normal };
+    Some((r - distance, normal))
+}
+
+// Distance from a point in obstacle space to the colider, 0.0 when inside
+pub fn colider_distance(colider: &Colider, point: Vec2) -> f32 {
+    let (vertices, rounding) = match colider_shape(colider) {
+        Some(shape) => shape,
+        None => return f32::MAX,
+    };
+
+    let n = vertices.len();
+    let inside = n >= 3 && (0..n)
+        .all(|i| edge_normal(vertices[i], vertices[(i + 1) % n]).dot(point - vertices[i]) <= 0.0);
+    if inside {
+        return 0.0;
+    }
+
+    let distance = (0..n)
+        .map(|i| point.distance(closest_point_on_segment(point, vertices[i], vertices[(i + 1) % n])))
+        .fold(f32::MAX, f32::min);
+    (distance - rounding).max(0.0)
+}
+
+// Pushes the ball out of an obstacle it overlaps. Every obstacle colider
+// (box, polygon, capsule, circle) is a rounded convex outline, so one
+// contact routine covers them all; anything else is ignored.
+fn resolve_colission(po_a: &mut PhysicsObject, tr_b: &Transform, po_b: &PhysicsObject, material: &PhysicsMaterial) {
+    if let Colider::CircleColider(ball_r) = po_a.colider {
+        if let Some((vertices, rounding)) = colider_shape(&po_b.colider) {
+            let local = to_local(tr_b, po_a.position - Vec2::new(tr_b.translation.x, tr_b.translation.z));
+            if let Some((depth, normal)) = penetration_convex(local, ball_r + rounding, &vertices) {
+                let norm = to_world(tr_b, normal);
+                po_a.position += depth * norm;
+                let surface = surface_velocity(po_a.position, tr_b, po_b);
+                bounce(po_a, norm, material, surface);
+            }
+        }
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Vec<Vec2> {
+        colider_shape(&Colider::BoxColider(2.0, 2.0)).unwrap().0
     }
-}
\ No newline at end of file
+
+    fn assert_hit(hit: Option<(f32, Vec2)>, t: f32, normal: Vec2) {
+        let (hit_t, hit_normal) = hit.expect("no hit");
+        assert!((hit_t - t).abs() < 1e-4, "hit at {} instead of {}", hit_t, t);
+        assert!(hit_normal.distance(normal) < 1e-4, "normal {} instead of {}", hit_normal, normal);
+    }
+
+    #[test]
+    fn sweep_hits_the_facing_edge() {
+        let hit = sweep_circle_convex(Vec2::new(-3.0, 0.0), Vec2::new(4.0, 0.0), 0.5, &square());
+        assert_hit(hit, 0.375, Vec2::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn sweep_hits_the_rounded_corner() {
+        // Passes above the left edge and clips the top left corner
+        let hit = sweep_circle_convex(Vec2::new(-3.0, 1.3), Vec2::new(6.0, 0.0), 0.5, &square());
+        assert_hit(hit, 0.8 / 3.0, Vec2::new(-0.8, 0.6));
+    }
+
+    #[test]
+    fn sweep_misses_beside_the_outline() {
+        assert!(sweep_circle_convex(Vec2::new(-3.0, 1.6), Vec2::new(6.0, 0.0), 0.5, &square()).is_none());
+    }
+
+    #[test]
+    fn sweep_stops_short_of_the_outline() {
+        assert!(sweep_circle_convex(Vec2::new(-3.0, 0.0), Vec2::new(1.0, 0.0), 0.5, &square()).is_none());
+    }
+
+    #[test]
+    fn sweep_ignores_moving_away() {
+        assert!(sweep_circle_convex(Vec2::new(-1.6, 0.0), Vec2::new(-2.0, 0.0), 0.5, &square()).is_none());
+    }
+
+    #[test]
+    fn sweep_against_a_circle() {
+        let (vertices, rounding) = colider_shape(&Colider::CircleColider(1.0)).unwrap();
+        let hit = sweep_circle_convex(Vec2::new(-5.0, 0.0), Vec2::new(10.0, 0.0), 0.5 + rounding, &vertices);
+        assert_hit(hit, 0.35, Vec2::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn sweep_against_a_clockwise_polygon() {
+        // Winding is fixed up by colider_shape, the hit is the same as for the box
+        let points = vec![(-1.0, -1.0), (-1.0, 1.0), (1.0, 1.0), (1.0, -1.0)];
+        let (vertices, _) = colider_shape(&Colider::PolygonColider(points)).unwrap();
+        let hit = sweep_circle_convex(Vec2::new(0.0, 3.0), Vec2::new(0.0, -4.0), 0.5, &vertices);
+        assert_hit(hit, 0.375, Vec2::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn fast_spinners_turn_at_most_a_ball_radius_per_step() {
+        let colider = Colider::CapsuleColider((0.0, 0.0), (3.0, 0.0), 0.2);
+        let motion = ObstacleMotion::Rotate { pivot: (0.0, 0.0), speed: 3600.0 };
+        let mut mover = ObstacleMover::new(motion, Vec2::ZERO, 0.0, &colider);
+        let (mut transform, mut po) = (Transform::default(), PhysicsObject::default());
+        let tip = |transform: &Transform| transform.rotation * Vec3::new(3.2, 0.0, 0.0);
+
+        obstacle_move(&mut transform, &mut po, &mut mover, 0.0);
+        for _ in 0..10 {
+            let start = tip(&transform);
+            obstacle_move(&mut transform, &mut po, &mut mover, PHYSICS_TIMESTEP);
+            assert!(tip(&transform).distance(start) <= BALL_RADIUS + 1e-4);
+            assert!(po.angular_speed.abs() * 3.2 * PHYSICS_TIMESTEP <= BALL_RADIUS + 1e-4);
+        }
+    }
+
+    #[test]
+    fn slow_spinners_keep_their_speed() {
+        let colider = Colider::CapsuleColider((-1.5, 0.0), (1.5, 0.0), 0.2);
+        let motion = ObstacleMotion::Rotate { pivot: (0.0, 0.0), speed: 60.0 };
+        let mut mover = ObstacleMover::new(motion, Vec2::ZERO, 0.0, &colider);
+        let (mut transform, mut po) = (Transform::default(), PhysicsObject::default());
+        obstacle_move(&mut transform, &mut po, &mut mover, PHYSICS_TIMESTEP);
+        assert!((po.angular_speed - 60.0_f32.to_radians()).abs() < 1e-5);
+        assert!((mover.time - PHYSICS_TIMESTEP).abs() < 1e-6);
+    }
+}
@@ -1,9 +1,26 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+use crate::arena::*;
+use crate::ball::*;
+use crate::ball_anim::ball_anim_step;
+use crate::hole::*;
+use crate::obstacle::*;
+use crate::surface::*;
+use crate::broadphase::*;
+use crate::replay::*;
+use crate::score::RunRes;
+
+use super::GameState;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Colider {
     BoxColider(f32, f32),
     CircleColider(f32),
+    // Convex outline (x, z), either winding
+    PolygonColider(Vec<(f32, f32)>),
+    // Segment between two points (x, z) rounded by a radius
+    CapsuleColider((f32, f32), (f32, f32), f32),
 }
 
 
@@ -11,8 +28,13 @@ pub enum Colider {
 pub struct PhysicsObject {
     pub speed: Vec2,
     pub acc: Vec2,
-    pub max_acc: Vec2,
     pub colider: Colider,
+    // Arena space (x, z) position of dynamic objects, stepped at PHYSICS_TIMESTEP
+    pub position: Vec2,
+    // Position before the last step, used to interpolate rendering
+    pub prev_position: Vec2,
+    // Turn rate about the up axis in radians per second, only moving obstacles spin
+    pub angular_speed: f32,
 }
 
 impl Default for PhysicsObject {
@@ -20,14 +42,253 @@ impl Default for PhysicsObject {
         Self { 
             speed: Default::default(), 
             acc: Default::default(), 
-            max_acc: Vec2::new(1.0, 1.0), 
-            colider: Colider::BoxColider(1.0, 1.0) 
+            colider: Colider::BoxColider(1.0, 1.0),
+            position: Default::default(),
+            prev_position: Default::default(),
+            angular_speed: Default::default(),
         }
     }
 }
 
+// How a surface treats the ball, attached to obstacles, floor zones and the arena itself
+#[derive(Component, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhysicsMaterial {
+    // Coulomb friction coefficient, grip on the floor and drag along walls
+    pub friction: f32,
+    // Share of the ball speed lost per second rolling over the surface
+    pub rolling_resistance: f32,
+    // Share of the speed into a wall that is kept after bouncing off it
+    pub restitution: f32,
+}
+
+impl PhysicsMaterial {
+    pub const WOOD: PhysicsMaterial = PhysicsMaterial { friction: 0.3, rolling_resistance: 0.2, restitution: 0.2 };
+    pub const RUBBER: PhysicsMaterial = PhysicsMaterial { friction: 0.8, rolling_resistance: 0.5, restitution: 0.9 };
+    pub const ICE: PhysicsMaterial = PhysicsMaterial { friction: 0.02, rolling_resistance: 0.0, restitution: 0.1 };
+    pub const SAND: PhysicsMaterial = PhysicsMaterial { friction: 0.6, rolling_resistance: 3.0, restitution: 0.0 };
+}
+
+impl Default for PhysicsMaterial {
+    fn default() -> Self {
+        PhysicsMaterial::WOOD
+    }
+}
+
+impl PhysicsObject {
+    // Moves the object without interpolating from the old position
+    pub fn teleport(&mut self, position: Vec2) {
+        self.position = position;
+        self.prev_position = position;
+    }
+
+    // Speed of the point `offset` away from the object's origin, ω × offset
+    // for a spin about the up axis
+    pub fn velocity_at(&self, offset: Vec2) -> Vec2 {
+        self.speed + self.angular_speed * Vec2::new(offset.y, -offset.x)
+    }
+}
+
+pub const PHYSICS_TIMESTEP: f32 = 1.0 / 120.0;
+// Arena units per second squared, tuned for play rather than realism
+pub const DEFAULT_GRAVITY: f32 = 4.0;
+// Upper bound on steps per frame so a long hitch does not stall the game
+const PHYSICS_MAX_STEPS: u32 = 12;
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PhysicsLabel {
+    Step,
+    Interpolate,
+}
+
+#[derive(Default)]
+pub struct PhysicsClock {
+    accumulator: f32,
+    // How far the rendered frame is between the last two physics steps
+    pub alpha: f32,
+}
+
+pub struct PhysicsSettings {
+    pub gravity: f32,
+}
+
+impl Default for PhysicsSettings {
+    fn default() -> Self {
+        PhysicsSettings { gravity: DEFAULT_GRAVITY }
+    }
+}
+
+pub struct PhysicsPlugin;
+
+impl Plugin for PhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PhysicsClock>()
+            .init_resource::<PhysicsSettings>()
+            .add_system_set(SystemSet::on_enter(GameState::Running).with_system(physics_clock_reset))
+            .add_system_set(
+                SystemSet::on_update(GameState::Running)
+                    .with_system(physics_step_system.label(PhysicsLabel::Step))
+                    .with_system(physics_interpolate_system.label(PhysicsLabel::Interpolate).after(PhysicsLabel::Step)));
+    }
+}
+
+fn physics_clock_reset(
+    mut clock: ResMut<PhysicsClock>,
+    mut ball_query: Query<(&mut PhysicsObject, &mut BallComponent)>,
+) {
+    *clock = PhysicsClock::default();
+    for (mut ball_po, mut ball) in ball_query.iter_mut() {
+        ball_po.prev_position = ball_po.position;
+        ball.prev_orientation = ball.orientation;
+    }
+}
+
+// Keep the obstacle and hole queries apart from the mutable ball query
+type ObstacleFilter = (With<ObstacleComponent>, Without<BallComponent>);
+type HoleFilter = (Without<BallComponent>, Without<ObstacleComponent>);
+
+// Runs as many fixed steps as the frame time allows, the outcome
+// of a step only depends on the previous state and the arena tilt
+#[allow(clippy::too_many_arguments)]
+fn physics_step_system(
+    time: Res<Time>,
+    mut clock: ResMut<PhysicsClock>,
+    settings: Res<PhysicsSettings>,
+    broadphase: Res<Broadphase>,
+    mut replay: ResMut<ReplayRes>,
+    mut run: ResMut<RunRes>,
+    mut game_state: ResMut<State<GameState>>,
+    mut arena_query: Query<(&mut Rotator, &Arena, &PhysicsMaterial)>,
+    mut ball_query: Query<(&mut PhysicsObject, &mut BallComponent)>,
+    mut obstacle_query: Query<(&mut Transform, &mut PhysicsObject, &PhysicsMaterial), ObstacleFilter>,
+    mut mover_query: Query<&mut ObstacleMover>,
+    zone_query: ZoneQuery,
+    hole_query: Query<(&Transform, &PhysicsObject, &HoleComponent), HoleFilter>,
+) {
+    let (mut rotator, arena, arena_material) = arena_query.single_mut();
+
+    clock.accumulator += time.delta_seconds();
+    let mut steps = 0;
+    while clock.accumulator >= PHYSICS_TIMESTEP {
+        if steps >= PHYSICS_MAX_STEPS {
+            clock.accumulator = 0.0;
+            break;
+        }
+        clock.accumulator -= PHYSICS_TIMESTEP;
+        steps += 1;
+
+        // The tilt of every step is all a replay needs to reproduce it
+        replay.tilt(&mut rotator.angle);
+        run.time += PHYSICS_TIMESTEP;
 
+        // Obstacles move first, the balls then bounce off them where they are now
+        for &(_, entity) in broadphase.moving.iter() {
+            if let (Ok(mut mover), Ok((mut transform, mut po, _))) = (mover_query.get_mut(entity), obstacle_query.get_mut(entity)) {
+                obstacle_move(&mut transform, &mut po, &mut mover, PHYSICS_TIMESTEP);
+            }
+        }
+
+        let obstacles_around = |ball_po: &PhysicsObject| -> Vec<_> {
+            broadphase.obstacles
+                .query_around(ball_po.position, obstacle_step_reach(ball_po, PHYSICS_TIMESTEP))
+                .into_iter()
+                .chain(broadphase.moving.iter().copied())
+                .filter_map(|(_, entity)| obstacle_query.get(entity).ok())
+                .collect()
+        };
+
+        for (mut ball_po, mut ball) in ball_query.iter_mut() {
+            if ball.state != BallState::Rolling {
+                ball_anim_step(&mut ball, &mut ball_po, PHYSICS_TIMESTEP);
+                // Nothing to interpolate while it is out of play
+                ball_po.prev_position = ball_po.position;
+                ball.prev_orientation = ball.orientation;
+                continue;
+            }
+
+            let floor = floor_material(ball_po.position, arena_material, &zone_query);
+            ball_step(&mut ball_po, &rotator, &floor, settings.gravity, PHYSICS_TIMESTEP);
+            let obstacles = obstacles_around(&ball_po);
+            obstacle_step(&mut ball_po, &obstacles, PHYSICS_TIMESTEP);
+
+            if ball_out_of_bounds(&ball_po, arena) {
+                reset_ball(&mut ball);
+                run.falls += 1;
+                continue;
+            }
+
+            let holes: Vec<_> = broadphase.holes
+                .query_around(ball_po.position, colider_bounds(&ball_po.colider))
+                .into_iter()
+                .filter_map(|(_, entity)| hole_query.get(entity).ok())
+                .collect();
+            if let Some((outcome, hole)) = hole_step(&mut ball_po, &mut ball, &holes, settings.gravity, PHYSICS_TIMESTEP) {
+                ball.state = BallState::Dropping {
+                    time: 0.0,
+                    hole,
+                    goal: matches!(outcome, HoleOutcome::Goal),
+                };
+                ball_po.speed = Vec2::ZERO;
+                if matches!(outcome, HoleOutcome::Trap) {
+                    run.falls += 1;
+                }
+            }
+        }
 
+        let mut ball_pairs = ball_query.iter_combinations_mut();
+        while let Some([(mut po_a, ball_a), (mut po_b, ball_b)]) = ball_pairs.fetch_next() {
+            if ball_a.state == BallState::Rolling && ball_b.state == BallState::Rolling {
+                ball_ball_step(&mut po_a, &mut po_b);
+                // Pushing the balls apart must not put either into a wall
+                let (obstacles_a, obstacles_b) = (obstacles_around(&po_a), obstacles_around(&po_b));
+                obstacle_push_out(&mut po_a, &obstacles_a);
+                obstacle_push_out(&mut po_b, &obstacles_b);
+            }
+        }
+
+        for (ball_po, mut ball) in ball_query.iter_mut() {
+            if ball.state == BallState::Rolling {
+                ball_roll(&ball_po, &mut ball);
+            }
+        }
+
+        // Kept with the recording for ghosts to follow
+        let mut positions: Vec<_> = ball_query.iter()
+            .map(|(ball_po, ball)| (ball.index, (ball.state == BallState::Rolling).then(|| (ball_po.position.x, ball_po.position.y))))
+            .collect();
+        positions.sort_by_key(|(index, _)| *index);
+        replay.record_balls(positions.into_iter().map(|(_, position)| position).collect());
+
+        let sunk = ball_query.iter().filter(|(_, ball)| ball.state == BallState::Sunk).count();
+        if sunk >= arena.balls_to_win {
+            // When a pause, restart or the editor was queued first the sunk
+            // balls are counted again once the level runs, the remaining
+            // steps wait but the interpolation still has to be updated
+            game_state.set(GameState::LevelComplete).ok();
+            break;
+        }
+    }
+
+    clock.alpha = clock.accumulator / PHYSICS_TIMESTEP;
+}
+
+fn physics_interpolate_system(
+    clock: Res<PhysicsClock>,
+    mut ball_query: Query<(&mut Transform, &PhysicsObject, &BallComponent)>,
+) {
+    for (mut ball_transform, ball_po, ball) in ball_query.iter_mut() {
+        // Balls out of play are placed by their animation
+        if ball.state != BallState::Rolling {
+            continue;
+        }
+
+        let position = ball_po.prev_position.lerp(ball_po.position, clock.alpha);
+        ball_transform.translation.x = position.x;
+        ball_transform.translation.z = position.y;
+        ball_transform.translation.y = BALL_RADIUS - ball.sink;
+        ball_transform.rotation = ball.prev_orientation.slerp(ball.orientation, clock.alpha);
+    }
+}
 
 // physics_update
 // fn physiscs_update(
@@ -0,0 +1,226 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::app::AppExit;
+use serde::{Deserialize, Serialize};
+
+use crate::level::*;
+use crate::level_asset::*;
+use crate::physics::*;
+
+use super::GameState;
+
+// One attempt at a level as it is stored on disk (*.replay.ron). The physics
+// only depends on the arena tilt of every step, so the level, the gravity and
+// the tilt per step are enough to play the attempt back exactly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub level: LevelAsset,
+    pub gravity: f32,
+    // Seconds per entry in `tilt`, PHYSICS_TIMESTEP of the recording build
+    pub timestep: f32,
+    // Arena angle (Rotator::angle) used by each physics step, step n starts at n * timestep
+    pub tilt: Vec<(f32, f32)>,
+    // Position of every ball after each step, None while it is out of play.
+    // Not needed for playback, ghosts follow it without running the physics.
+    #[serde(default)]
+    pub balls: Vec<Vec<Option<(f32, f32)>>>,
+    // Whether the attempt ended with the level won
+    #[serde(default)]
+    pub finished: bool,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Replay, anyhow::Error> {
+        load_ron(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        save_ron(self, path)
+    }
+
+    // Seconds from the start of the attempt to its last step
+    pub fn duration(&self) -> f32 {
+        self.tilt.len() as f32 * self.timestep
+    }
+}
+
+// Records the attempt being played or feeds a loaded one to the physics
+#[derive(Default)]
+pub struct ReplayRes {
+    // Attempt in progress, None until the first one starts
+    pub recording: Option<Replay>,
+    // Where finished and abandoned attempts are written, from --record
+    pub record_path: Option<PathBuf>,
+    // Replay given with --replay, played instead of the player input
+    pub playback: Option<Replay>,
+    // Replay given with --ghost, raced against instead of the personal best
+    pub ghost_file: Option<Replay>,
+    // Fastest finished attempt at the current level and the level it belongs to
+    pub best: Option<Replay>,
+    pub best_key: Option<String>,
+    // Physics steps taken in the current attempt
+    pub step: usize,
+}
+
+impl ReplayRes {
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    // Run the ghost balls follow
+    pub fn ghost(&self) -> Option<&Replay> {
+        self.ghost_file.as_ref().or(self.best.as_ref())
+    }
+
+    // Called once per physics step before the balls move: hands the recorded
+    // tilt to the physics when playing back, remembers it otherwise
+    pub fn tilt(&mut self, angle: &mut Vec2) {
+        if let Some(playback) = &self.playback {
+            // Past the end the arena stays where the recording left it
+            if let Some(&(x, y)) = playback.tilt.get(self.step).or_else(|| playback.tilt.last()) {
+                *angle = Vec2::new(x, y);
+            }
+        } else if let Some(recording) = &mut self.recording {
+            recording.tilt.push((angle.x, angle.y));
+        }
+        self.step += 1;
+    }
+
+    // Called after each physics step with the ball positions ordered by ball index
+    pub fn record_balls(&mut self, positions: Vec<Option<(f32, f32)>>) {
+        if let Some(recording) = &mut self.recording {
+            recording.balls.push(positions);
+        }
+    }
+
+    // Stops recording the attempt in progress and writes it to the --record file
+    fn finish(&mut self, finished: bool) -> Option<Replay> {
+        let mut recording = self.recording.take()?;
+        if recording.tilt.is_empty() {
+            return None;
+        }
+        recording.finished = finished;
+        if let Some(path) = &self.record_path {
+            match recording.save(path) {
+                Ok(()) => info!("Replay of {:.2}s saved to {}", recording.duration(), path.display()),
+                Err(err) => error!("Replay could not be saved: {}", err),
+            }
+        }
+        Some(recording)
+    }
+}
+
+pub fn best_replay_path(key: &str) -> PathBuf {
+    save_dir().join("replays").join(format!("{}.best.replay.ron", key))
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ReplayRes>()
+            .add_system_set(SystemSet::on_enter(GameState::Running).with_system(replay_start))
+            .add_system_set(SystemSet::on_enter(GameState::LevelComplete).with_system(replay_complete))
+            .add_system_to_stage(CoreStage::Last, replay_exit);
+    }
+}
+
+// An attempt starts whenever the balls have grown in: at the start of a
+// level, after a restart and after leaving the editor
+fn replay_start(
+    mut replay: ResMut<ReplayRes>,
+    settings: Res<PhysicsSettings>,
+    asset_server: Res<AssetServer>,
+    levels: Res<Assets<LevelAsset>>,
+    level_assets: Res<LevelRes>,
+) {
+    replay.finish(false);
+    replay.step = 0;
+    if replay.is_playing() {
+        return;
+    }
+
+    let key = level_assets.current_key(&asset_server);
+    if key != replay.best_key {
+        replay.best = key.as_deref().and_then(|key| Replay::load(&best_replay_path(key)).ok());
+        replay.best_key = key;
+    }
+
+    if let Some(level) = level_assets.current_level().and_then(|handle| levels.get(handle)) {
+        replay.recording = Some(Replay {
+            level: level.clone(),
+            gravity: settings.gravity,
+            timestep: PHYSICS_TIMESTEP,
+            tilt: Vec::new(),
+            balls: Vec::new(),
+            finished: false,
+        });
+    }
+}
+
+// A faster finish than the personal best becomes the new one
+fn replay_complete(
+    mut replay: ResMut<ReplayRes>,
+) {
+    let (recording, key) = match (replay.finish(true), replay.best_key.clone()) {
+        (Some(recording), Some(key)) => (recording, key),
+        _ => return,
+    };
+    if replay.best.as_ref().map_or(false, |best| best.duration() <= recording.duration()) {
+        return;
+    }
+
+    match recording.save(&best_replay_path(&key)) {
+        Ok(()) => info!("New personal best of {:.2}s", recording.duration()),
+        Err(err) => error!("Personal best could not be saved: {}", err),
+    }
+    replay.best = Some(recording);
+}
+
+fn replay_exit(
+    mut exit_events: EventReader<AppExit>,
+    mut replay: ResMut<ReplayRes>,
+) {
+    if exit_events.iter().next().is_some() {
+        replay.finish(false);
+    }
+}
+
+// --record <path> keeps the last attempt in a replay file,
+// --replay <path> plays one back, --ghost <path> races against one
+pub fn replay_from_args() -> ReplayRes {
+    let args: Vec<String> = std::env::args().collect();
+    let mut replay = ReplayRes::default();
+
+    if let Some(i) = args.iter().position(|arg| arg == "--record") {
+        match args.get(i + 1) {
+            Some(path) => replay.record_path = Some(PathBuf::from(path)),
+            None => eprintln!("--record expects a file name"),
+        }
+    }
+
+    if let Some(i) = args.iter().position(|arg| arg == "--replay") {
+        match args.get(i + 1).map(|path| (path, Replay::load(Path::new(path)))) {
+            Some((_, Ok(playback))) => {
+                if playback.timestep != PHYSICS_TIMESTEP {
+                    eprintln!("Replay was recorded with {}s physics steps, it will not play back exactly", playback.timestep);
+                }
+                replay.playback = Some(playback);
+            },
+            Some((path, Err(err))) => eprintln!("Replay {} can not be read: {}", path, err),
+            None => eprintln!("--replay expects a file name"),
+        }
+    }
+
+    if let Some(i) = args.iter().position(|arg| arg == "--ghost") {
+        match args.get(i + 1).map(|path| (path, Replay::load(Path::new(path)))) {
+            Some((_, Ok(ghost))) => replay.ghost_file = Some(ghost),
+            Some((path, Err(err))) => eprintln!("Replay {} can not be read: {}", path, err),
+            None => eprintln!("--ghost expects a file name"),
+        }
+    }
+
+    replay
+}
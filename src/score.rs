@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::level::*;
+use crate::level_asset::{load_ron, save_dir, save_ron};
+use crate::replay::ReplayRes;
+
+use super::GameState;
+
+// Times kept per level
+pub const LEADERBOARD_SIZE: usize = 5;
+
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Leaderboard>()
+            .add_startup_system(hud_spawn)
+            .add_system(hud_update)
+            .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(run_reset))
+            .add_system_set(SystemSet::on_enter(GameState::RespawnGrow).with_system(run_start))
+            .add_system_set(SystemSet::on_enter(GameState::Running).with_system(run_start))
+            .add_system_set(SystemSet::on_enter(GameState::LevelComplete).with_system(run_complete.before(LevelCompleteLabel)));
+    }
+}
+
+// Attempt in progress and the levels finished so far. Time and falls are
+// counted by the physics steps, so they match the replay of the attempt.
+pub struct RunRes {
+    // Name the times are entered under, from --name
+    pub player: String,
+    // Records of the current level are filed under this, see LevelRes::current_key
+    pub key: Option<String>,
+    pub level: usize,
+    // Seconds of play in the current attempt
+    pub time: f32,
+    // Balls lost to traps or off the edge in the current attempt
+    pub falls: u32,
+    pub splits: Vec<Split>,
+}
+
+// Result of one finished level
+#[derive(Clone, Debug)]
+pub struct Split {
+    pub name: String,
+    pub key: Option<String>,
+    pub time: f32,
+    pub falls: u32,
+    // Place on the leaderboard, None if it did not make it
+    pub rank: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub time: f32,
+    pub falls: u32,
+}
+
+// Fastest times per level as stored in save/leaderboard.ron
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub levels: BTreeMap<String, Vec<LeaderboardEntry>>,
+}
+
+impl Leaderboard {
+    pub fn path() -> PathBuf {
+        save_dir().join("leaderboard.ron")
+    }
+
+    pub fn load() -> Result<Leaderboard, anyhow::Error> {
+        load_ron(&Leaderboard::path())
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        save_ron(self, &Leaderboard::path())
+    }
+
+    pub fn entries(&self, key: &str) -> &[LeaderboardEntry] {
+        self.levels.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    // Zero based place of the new entry, None when it is slower than all kept ones.
+    // Equal times keep the earlier entry first.
+    pub fn insert(&mut self, key: &str, entry: LeaderboardEntry) -> Option<usize> {
+        let entries = self.levels.entry(key.to_string()).or_default();
+        let rank = entries.iter().position(|other| entry.time < other.time).unwrap_or(entries.len());
+        if rank >= LEADERBOARD_SIZE {
+            return None;
+        }
+        entries.insert(rank, entry);
+        entries.truncate(LEADERBOARD_SIZE);
+        Some(rank)
+    }
+}
+
+impl FromWorld for Leaderboard {
+    fn from_world(_world: &mut World) -> Self {
+        // Nothing is saved before the first level is finished
+        Leaderboard::load().unwrap_or_else(|_| Leaderboard { levels: BTreeMap::new() })
+    }
+}
+
+// 83.5 seconds as 1:23.50
+pub fn format_time(seconds: f32) -> String {
+    let hundredths = (seconds * 100.0).round() as u32;
+    format!("{}:{:02}.{:02}", hundredths / 6000, hundredths / 100 % 60, hundredths % 100)
+}
+
+// --name <player> sets the name on the leaderboard, the user name by default
+pub fn run_from_args() -> RunRes {
+    let args: Vec<String> = std::env::args().collect();
+    let mut player = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| String::from("Player"));
+
+    if let Some(i) = args.iter().position(|arg| arg == "--name") {
+        match args.get(i + 1) {
+            Some(name) => player = name.clone(),
+            None => eprintln!("--name expects a name"),
+        }
+    }
+
+    RunRes { player, key: None, level: 0, time: 0.0, falls: 0, splits: Vec::new() }
+}
+
+// The clock restarts with every attempt, like the replay recording, and
+// shows zero while the balls grow in
+fn run_start(
+    mut run: ResMut<RunRes>,
+    asset_server: Res<AssetServer>,
+    level_assets: Res<LevelRes>,
+) {
+    run.key = level_assets.current_key(&asset_server);
+    run.level = level_assets.current;
+    run.time = 0.0;
+    run.falls = 0;
+}
+
+// Splits add up from the level played after leaving the menu
+fn run_reset(
+    mut run: ResMut<RunRes>,
+) {
+    run.splits.clear();
+}
+
+fn run_complete(
+    mut run: ResMut<RunRes>,
+    mut leaderboard: ResMut<Leaderboard>,
+    replay: Res<ReplayRes>,
+) {
+    // Played back attempts are not the player's
+    let rank = match &run.key {
+        Some(key) if !replay.is_playing() => {
+            let entry = LeaderboardEntry { name: run.player.clone(), time: run.time, falls: run.falls };
+            let rank = leaderboard.insert(key, entry);
+            if rank.is_some() {
+                if let Err(err) = leaderboard.save() {
+                    error!("Leaderboard could not be saved: {}", err);
+                }
+            }
+            rank
+        },
+        _ => None,
+    };
+
+    let split = Split {
+        name: format!("Level {}", run.level + 1),
+        key: run.key.clone(),
+        time: run.time,
+        falls: run.falls,
+        rank,
+    };
+    info!("{} finished in {} with {} falls", split.name, format_time(split.time), split.falls);
+    run.splits.push(split);
+    run.time = 0.0;
+    run.falls = 0;
+}
+
+#[derive(Component)]
+struct HudTextComponent;
+
+fn hud_spawn(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/arial.ttf"),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                })
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(10.0),
+                    left: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            }))
+        .insert(HudTextComponent);
+}
+
+// Shown while a level is played, the splits so far are added up in the total
+fn hud_update(
+    game_state: Res<State<GameState>>,
+    run: Res<RunRes>,
+    leaderboard: Res<Leaderboard>,
+    mut text_query: Query<(&mut Text, &mut Visibility), With<HudTextComponent>>,
+) {
+    let playing = matches!(game_state.current(), GameState::RespawnGrow | GameState::Running | GameState::LevelComplete | GameState::Paused);
+    let best = run.key.as_deref()
+        .and_then(|key| leaderboard.entries(key).first())
+        .map_or(String::from("-"), |entry| format_time(entry.time));
+    let total: f32 = run.splits.iter().map(|split| split.time).sum::<f32>() + run.time;
+
+    for (mut text, mut visibility) in text_query.iter_mut() {
+        visibility.is_visible = playing;
+        text.sections[0].value = format!(
+            "Level {}   Time {}   Falls {}\nBest {}   Total {}",
+            run.level + 1, format_time(run.time), run.falls, best, format_time(total));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, time: f32) -> LeaderboardEntry {
+        LeaderboardEntry { name: name.to_string(), time, falls: 0 }
+    }
+
+    fn names(leaderboard: &Leaderboard, key: &str) -> Vec<String> {
+        leaderboard.entries(key).iter().map(|entry| entry.name.clone()).collect()
+    }
+
+    #[test]
+    fn insert_ranks_by_time() {
+        let mut leaderboard = Leaderboard { levels: BTreeMap::new() };
+        assert_eq!(leaderboard.insert("level", entry("b", 20.0)), Some(0));
+        assert_eq!(leaderboard.insert("level", entry("a", 10.0)), Some(0));
+        assert_eq!(leaderboard.insert("level", entry("c", 30.0)), Some(2));
+        assert_eq!(leaderboard.insert("level", entry("d", 15.0)), Some(1));
+        assert_eq!(names(&leaderboard, "level"), ["a", "d", "b", "c"]);
+    }
+
+    #[test]
+    fn insert_keeps_earlier_equal_times_first() {
+        let mut leaderboard = Leaderboard { levels: BTreeMap::new() };
+        leaderboard.insert("level", entry("first", 10.0));
+        assert_eq!(leaderboard.insert("level", entry("second", 10.0)), Some(1));
+        assert_eq!(names(&leaderboard, "level"), ["first", "second"]);
+    }
+
+    #[test]
+    fn insert_truncates_to_the_leaderboard_size() {
+        let mut leaderboard = Leaderboard { levels: BTreeMap::new() };
+        for i in 0..LEADERBOARD_SIZE {
+            leaderboard.insert("level", entry(&i.to_string(), 10.0 + i as f32));
+        }
+
+        // Slower than every kept time, or tied with the slowest
+        assert_eq!(leaderboard.insert("level", entry("slow", 99.0)), None);
+        assert_eq!(leaderboard.insert("level", entry("tied", 10.0 + (LEADERBOARD_SIZE - 1) as f32)), None);
+        assert_eq!(leaderboard.entries("level").len(), LEADERBOARD_SIZE);
+
+        // A faster one pushes the slowest out
+        assert_eq!(leaderboard.insert("level", entry("fast", 1.0)), Some(0));
+        assert_eq!(leaderboard.entries("level").len(), LEADERBOARD_SIZE);
+        assert_eq!(leaderboard.entries("level")[0].name, "fast");
+        assert!(leaderboard.entries("level").iter().all(|entry| entry.name != (LEADERBOARD_SIZE - 1).to_string()));
+    }
+
+    #[test]
+    fn insert_keeps_levels_apart() {
+        let mut leaderboard = Leaderboard { levels: BTreeMap::new() };
+        leaderboard.insert("one", entry("a", 10.0));
+        assert_eq!(leaderboard.insert("two", entry("b", 20.0)), Some(0));
+        assert_eq!(names(&leaderboard, "one"), ["a"]);
+        assert_eq!(names(&leaderboard, "two"), ["b"]);
+        assert!(leaderboard.entries("three").is_empty());
+    }
+
+    #[test]
+    fn format_time_pads_minutes_and_hundredths() {
+        assert_eq!(format_time(83.5), "1:23.50");
+        assert_eq!(format_time(5.004), "0:05.00");
+        assert_eq!(format_time(600.0), "10:00.00");
+    }
+}
@@ -0,0 +1,222 @@
+use bevy::prelude::*;
+use bevy::input::mouse::MouseWheel;
+
+use crate::arena::*;
+use crate::controls::*;
+
+use super::GameState;
+
+// Rows below the actions: tilt mode, mouse sensitivity, mouse invert
+const SETTINGS_OPTIONS: usize = 3;
+const SETTINGS_ROWS: usize = Action::ALL.len() + SETTINGS_OPTIONS;
+// Factor per Left/Right press and the range allowed around the default sensitivity
+const SETTINGS_SENSITIVITY_STEP: f32 = 1.25;
+const SETTINGS_SENSITIVITY_RANGE: f32 = 5.0;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SettingsRes>()
+            .add_system_set(SystemSet::on_enter(GameState::Settings).with_system(settings_enter).with_system(input_consume))
+            .add_system_set(SystemSet::on_update(GameState::Settings).with_system(settings_input))
+            .add_system_set(SystemSet::on_update(GameState::Settings).with_system(settings_text_update))
+            .add_system_set(SystemSet::on_exit(GameState::Settings).with_system(settings_exit));
+    }
+}
+
+// Selected row of the settings screen and whether the next key, button or
+// wheel turn becomes a new binding for it (action rows only)
+#[derive(Default)]
+pub struct SettingsRes {
+    selected: usize,
+    capturing: bool,
+    dirty: bool,
+}
+
+#[derive(Component)]
+struct SettingsTextComponent;
+
+fn settings_enter(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut settings: ResMut<SettingsRes>,
+) {
+    *settings = SettingsRes::default();
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/arial.ttf"),
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                })
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(40.0),
+                    left: Val::Px(40.0),
+                    ..default()
+                },
+                ..default()
+            }))
+        .insert(SettingsTextComponent);
+}
+
+fn settings_exit(
+    mut commands: Commands,
+    settings: Res<SettingsRes>,
+    map: Res<InputMap>,
+    text_query: Query<Entity, With<SettingsTextComponent>>,
+) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    if settings.dirty {
+        match map.save() {
+            Ok(()) => info!("Controls saved to {}", InputMap::path().display()),
+            Err(err) => error!("Controls could not be saved: {}", err),
+        }
+    }
+}
+
+// The screen itself is driven by fixed keys so a bad binding can always be undone
+#[allow(clippy::too_many_arguments)]
+fn settings_input(
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut settings: ResMut<SettingsRes>,
+    mut map: ResMut<InputMap>,
+    mut tilt: ResMut<TiltSettings>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    let action = Action::ALL.get(settings.selected).copied();
+    let wheel: f32 = wheel_events.iter().map(|event| event.y).sum();
+
+    if settings.capturing {
+        if keyboard.just_pressed(KeyCode::Escape) {
+            settings.capturing = false;
+            return;
+        }
+
+        let binding = keyboard.get_just_pressed().next().map(|key| Binding::Key(*key))
+            .or_else(|| mouse.get_just_pressed().next().map(|button| Binding::Mouse(*button)))
+            .or_else(|| gamepad_buttons.get_just_pressed().next().map(|button| Binding::Gamepad(button.button_type)))
+            .or(if wheel > 0.0 { Some(Binding::WheelUp) } else if wheel < 0.0 { Some(Binding::WheelDown) } else { None });
+
+        if let (Some(binding), Some(action)) = (binding, action) {
+            let bindings = map.bindings.entry(action).or_default();
+            if !bindings.contains(&binding) {
+                bindings.push(binding);
+                settings.dirty = true;
+            }
+            settings.capturing = false;
+        }
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Escape) {
+        game_state.pop().unwrap();
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Up) {
+        settings.selected = (settings.selected + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+    }
+    if keyboard.just_pressed(KeyCode::Down) {
+        settings.selected = (settings.selected + 1) % SETTINGS_ROWS;
+    }
+
+    let action = match action {
+        Some(action) => action,
+        None => {
+            settings_option_input(&keyboard, settings.selected - Action::ALL.len(), &mut tilt);
+            return;
+        },
+    };
+    if keyboard.just_pressed(KeyCode::Return) {
+        settings.capturing = true;
+    }
+    if keyboard.just_pressed(KeyCode::Back) || keyboard.just_pressed(KeyCode::Delete) {
+        map.bindings.insert(action, Vec::new());
+        settings.dirty = true;
+    }
+    if keyboard.just_pressed(KeyCode::F5) {
+        map.bindings.insert(action, InputMap::default_bindings(action));
+        settings.dirty = true;
+    }
+}
+
+// Left/Right change the option, Enter flips the switches, F5 restores the default
+fn settings_option_input(keyboard: &Input<KeyCode>, option: usize, tilt: &mut TiltSettings) {
+    let left = keyboard.just_pressed(KeyCode::Left);
+    let right = keyboard.just_pressed(KeyCode::Right);
+    let toggle = left || right || keyboard.just_pressed(KeyCode::Return);
+    let reset = keyboard.just_pressed(KeyCode::F5);
+    let defaults = TiltSettings::default();
+
+    match option {
+        0 if reset => tilt.mode = defaults.mode,
+        0 if toggle => tilt.mode = match tilt.mode {
+            TiltMode::Standard => TiltMode::MouseDrag,
+            TiltMode::MouseDrag => TiltMode::Standard,
+        },
+        1 if reset => tilt.mouse_sensitivity = defaults.mouse_sensitivity,
+        1 if left || right => {
+            let factor = if right { SETTINGS_SENSITIVITY_STEP } else { 1.0 / SETTINGS_SENSITIVITY_STEP };
+            tilt.mouse_sensitivity = (tilt.mouse_sensitivity * factor).clamp(
+                ARENA_MOUSE_SENSITIVITY / SETTINGS_SENSITIVITY_RANGE,
+                ARENA_MOUSE_SENSITIVITY * SETTINGS_SENSITIVITY_RANGE);
+        },
+        2 if reset => tilt.mouse_invert = defaults.mouse_invert,
+        2 if toggle => tilt.mouse_invert = !tilt.mouse_invert,
+        _ => {},
+    }
+}
+
+fn settings_text_update(
+    settings: Res<SettingsRes>,
+    map: Res<InputMap>,
+    tilt: Res<TiltSettings>,
+    mut text_query: Query<&mut Text, With<SettingsTextComponent>>,
+) {
+    let marker = |row: usize| if row == settings.selected { ">" } else { " " };
+    let mut lines = vec![String::from("CONTROLS"), String::new()];
+    for (i, action) in Action::ALL.iter().enumerate() {
+        let bindings = if settings.capturing && i == settings.selected {
+            String::from("press a key or button...")
+        } else {
+            map.bindings(*action).iter()
+                .map(|binding| binding.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        lines.push(format!("{} {:<16} {}", marker(i), action.name(), bindings));
+    }
+
+    let options = [
+        ("Tilt mode", tilt.mode.name().to_string()),
+        ("Mouse sensitivity", format!("{:.0}%", tilt.mouse_sensitivity / ARENA_MOUSE_SENSITIVITY * 100.0)),
+        ("Invert mouse", String::from(if tilt.mouse_invert { "on" } else { "off" })),
+    ];
+    lines.push(String::new());
+    lines.push(String::from("TILT"));
+    lines.push(String::new());
+    for (i, (name, value)) in options.iter().enumerate() {
+        lines.push(format!("{} {:<16} {}", marker(Action::ALL.len() + i), name, value));
+    }
+
+    lines.push(String::new());
+    lines.push(String::from("Up/Down: select   Enter: add binding   Backspace: clear   F5: defaults   Esc: back"));
+    lines.push(String::from("Left/Right: change option"));
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
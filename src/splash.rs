@@ -1,11 +1,16 @@
 use std::time::Duration;
 
-use bevy::app::AppExit;
 use bevy::prelude::*;
 use bevy::utils::Instant;
 
+use crate::level::LevelRes;
+use crate::score::*;
+
 use super::GameState;
 
+// Seconds the results stay up, the last one fades them out
+const SPLASH_DURATION: f32 = 10.0;
+
 #[derive(Component)]
 struct SplashTextComponent {
     timer: Timer,
@@ -36,10 +41,33 @@ impl Plugin for SplashPlugin {
     }
 }
 
+// Time and falls of every level with its leaderboard, the places the
+// player made are marked
 fn splash_enter(
     mut commands: Commands,
     spash_a: Res<SplashRes>,
+    run: Res<RunRes>,
+    leaderboard: Res<Leaderboard>,
 ) {
+    let mut results = String::from("\n");
+    for split in run.splits.iter() {
+        let record = match split.rank {
+            Some(0) => String::from("   NEW RECORD"),
+            Some(rank) => format!("   #{}", rank + 1),
+            None => String::new(),
+        };
+        results.push_str(&format!("\n{}   {}   {} falls{}\n", split.name, format_time(split.time), split.falls, record));
+
+        let entries = split.key.as_deref().map_or(&[][..], |key| leaderboard.entries(key));
+        for (i, entry) in entries.iter().enumerate() {
+            let marker = if split.rank == Some(i) { ">" } else { " " };
+            results.push_str(&format!("{} {}. {}   {}   {} falls\n", marker, i + 1, entry.name, format_time(entry.time), entry.falls));
+        }
+    }
+    let total: f32 = run.splits.iter().map(|split| split.time).sum();
+    let falls: u32 = run.splits.iter().map(|split| split.falls).sum();
+    results.push_str(&format!("\nTotal   {}   {} falls", format_time(total), falls));
+
     commands.spawn_bundle(
         TextBundle::from_sections(
             vec![
@@ -47,10 +75,19 @@ fn splash_enter(
                     style: 
                         TextStyle { 
                             font: spash_a.font_handle.clone(),
-                            font_size: 100.0,
+                            font_size: 60.0,
+                            ..default()
+                        },
+                    value: String::from("CAMPAIGN COMPLETE"),
+                },
+                TextSection {
+                    style:
+                        TextStyle {
+                            font: spash_a.font_handle.clone(),
+                            font_size: 20.0,
                             ..default()
                         },
-                    value: String::from("YOU WIN!"),
+                    value: results,
                 }]).with_style(
                     Style {
                         position_type: PositionType::Relative,
@@ -69,23 +106,26 @@ fn splash_enter(
             )
         .insert(
             SplashTextComponent {
-                timer: Timer::new(Duration::from_secs(5), false),
+                timer: Timer::new(Duration::from_secs_f32(SPLASH_DURATION), false),
                 start_time: Some(Instant::now()),
             });
     
 }
 
+// The campaign starts over from the main menu
 fn splash_update(
     mut commands: Commands,
     time: Res<Time>,
     mut query: Query<(Entity, &mut SplashTextComponent)>,
-    mut exit: EventWriter<AppExit>,
+    mut level_assets: ResMut<LevelRes>,
+    mut game_state: ResMut<State<GameState>>,
 ) {
     for (ent, mut spc) in query.iter_mut() {
         spc.timer.tick(time.delta());
         if spc.timer.finished() {
             commands.entity(ent).despawn();
-            exit.send(AppExit);
+            level_assets.current = 0;
+            game_state.set(GameState::MainMenu).unwrap();
         }
     }
 }
@@ -104,7 +144,7 @@ fn fade_animation(
         if let Some(start_instant) = stc.start_time {
             let delta = Instant::now() - start_instant;
             
-            let ratio = (5.0 - delta.as_secs_f32()).clamp(0.0, 1.0);
+            let ratio = (SPLASH_DURATION - delta.as_secs_f32()).clamp(0.0, 1.0);
 
             for i in text.sections.iter_mut() {
                 i.style.color.set_a(ratio);
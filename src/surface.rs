@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::physics::*;
+use crate::ball::*;
+use crate::obstacle::{colider_distance, ObstacleComponent};
+
+// Floor patches stick out just enough above the arena to not flicker,
+// staying below the holes (0.001) so those remain visible
+pub const ZONE_HEIGHT: f32 = 0.0005;
+// Raise every following zone a bit so overlapping ones do not flicker either
+pub const ZONE_LAYER_STEP: f32 = 0.00001;
+
+// Part of the arena floor with its own physics material (ice, sand, ...)
+#[derive(Component)]
+pub struct ZoneComponent {
+    // Position in the level file, later zones win where they overlap
+    pub order: usize,
+}
+
+#[derive(Bundle)]
+pub struct ZoneBundle {
+    pub zone_comp: ZoneComponent,
+    pub po: PhysicsObject,
+    pub material: PhysicsMaterial,
+    #[bundle]
+    pub pbr: PbrBundle,
+}
+
+// Surface names used in level files, Custom takes explicit values
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Surface {
+    Wood,
+    Rubber,
+    Ice,
+    Sand,
+    Custom(PhysicsMaterial),
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Surface::Wood
+    }
+}
+
+impl Surface {
+    pub fn physics(&self) -> PhysicsMaterial {
+        match self {
+            Surface::Wood => PhysicsMaterial::WOOD,
+            Surface::Rubber => PhysicsMaterial::RUBBER,
+            Surface::Ice => PhysicsMaterial::ICE,
+            Surface::Sand => PhysicsMaterial::SAND,
+            Surface::Custom(material) => *material,
+        }
+    }
+
+    // Flat color of the surface, the wood texture averages to a light brown
+    pub fn color(&self) -> Color {
+        match self {
+            Surface::Wood => Color::rgb_u8(190, 150, 100),
+            Surface::Rubber => Color::rgb_u8(40, 40, 45),
+            Surface::Ice => Color::rgb_u8(190, 225, 245),
+            Surface::Sand => Color::rgb_u8(215, 190, 130),
+            Surface::Custom(_) => Color::rgb_u8(140, 140, 140),
+        }
+    }
+
+    // Next preset, used by the editor to cycle through them
+    pub fn next(&self) -> Surface {
+        match self {
+            Surface::Wood => Surface::Rubber,
+            Surface::Rubber => Surface::Ice,
+            Surface::Ice => Surface::Sand,
+            Surface::Sand | Surface::Custom(_) => Surface::Wood,
+        }
+    }
+}
+
+pub struct SurfaceRes {
+    pub wood: Handle<StandardMaterial>,
+    pub rubber: Handle<StandardMaterial>,
+    pub ice: Handle<StandardMaterial>,
+    pub sand: Handle<StandardMaterial>,
+    pub custom: Handle<StandardMaterial>,
+}
+
+impl SurfaceRes {
+    pub fn material(&self, surface: &Surface) -> Handle<StandardMaterial> {
+        match surface {
+            Surface::Wood => self.wood.clone(),
+            Surface::Rubber => self.rubber.clone(),
+            Surface::Ice => self.ice.clone(),
+            Surface::Sand => self.sand.clone(),
+            Surface::Custom(_) => self.custom.clone(),
+        }
+    }
+}
+
+impl FromWorld for SurfaceRes {
+    fn from_world(world: &mut World) -> Self {
+        let tex_handle = world.resource::<AssetServer>().load("wood.png");
+
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut plain = |color: Color, roughness: f32| materials.add(StandardMaterial {
+            base_color: color,
+            metallic: 0.0,
+            reflectance: 0.2,
+            perceptual_roughness: roughness,
+            ..default()
+        });
+
+        let rubber = plain(Surface::Rubber.color(), 0.9);
+        let ice = plain(Surface::Ice.color(), 0.1);
+        let sand = plain(Surface::Sand.color(), 1.0);
+        let custom = plain(Surface::Custom(PhysicsMaterial::WOOD).color(), 0.6);
+        let wood = materials.add(StandardMaterial {
+            base_color_texture: Some(tex_handle),
+            metallic: 0.0,
+            reflectance: 0.0,
+            perceptual_roughness: 1.0,
+            ..default()
+        });
+
+        SurfaceRes { wood, rubber, ice, sand, custom }
+    }
+}
+
+pub struct SurfacePlugin;
+
+impl Plugin for SurfacePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SurfaceRes>();
+    }
+}
+
+// Floor zones as the physics step reads them, apart from the balls and obstacles it moves
+pub type ZoneQuery<'w, 's> = Query<'w, 's,
+    (&'static Transform, &'static PhysicsObject, &'static PhysicsMaterial, &'static ZoneComponent),
+    (Without<BallComponent>, Without<ObstacleComponent>)>;
+
+// Material of the floor under the ball, zones listed later win where they overlap
+pub fn floor_material(
+    position: Vec2,
+    arena_material: &PhysicsMaterial,
+    zone_query: &ZoneQuery,
+) -> PhysicsMaterial {
+    let mut floor: Option<(usize, PhysicsMaterial)> = None;
+    for (zone_transform, zone_po, zone_material, zone) in zone_query.iter() {
+        let diff = position - Vec2::new(zone_transform.translation.x, zone_transform.translation.z);
+        let local = zone_transform.rotation.inverse() * Vec3::new(diff.x, 0.0, diff.y);
+        if colider_distance(&zone_po.colider, Vec2::new(local.x, local.z)) <= 0.0 &&
+            floor.map_or(true, |(order, _)| zone.order > order) {
+            floor = Some((zone.order, *zone_material));
+        }
+    }
+    floor.map_or(*arena_material, |(_, material)| material)
+}
@@ -0,0 +1,482 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::ball::BALL_RADIUS;
+use crate::level_asset::*;
+use crate::obstacle::{colider_distance, ObstacleMotion};
+use crate::physics::Colider;
+
+// Resolution of the grid used for the reachability check
+const VALIDATE_GRID_STEP: f32 = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelError {
+    HoleOutOfBounds { hole: usize },
+    ObstacleOverlapsHole { obstacle: usize, hole: usize },
+    BallStartOutOfBounds { ball: usize },
+    BallStartInObstacle { ball: usize, obstacle: usize },
+    BallStartInHole { ball: usize, hole: usize },
+    BallStartsOverlap { ball: usize, other: usize },
+    BallsToWin { required: usize, balls: usize },
+    MissingFinalHole,
+    MultipleFinalHoles { count: usize },
+    MotionKeysUnordered { obstacle: usize },
+    PolygonTooFewPoints { obstacle: usize },
+    PolygonNotConvex { obstacle: usize },
+    GoalUnreachable { ball: usize },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::HoleOutOfBounds { hole } =>
+                write!(f, "hole {} is outside of the arena", hole),
+            LevelError::ObstacleOverlapsHole { obstacle, hole } =>
+                write!(f, "obstacle {} overlaps hole {}", obstacle, hole),
+            LevelError::BallStartOutOfBounds { ball } =>
+                write!(f, "ball {} start is outside of the arena", ball),
+            LevelError::BallStartInObstacle { ball, obstacle } =>
+                write!(f, "ball {} start is inside obstacle {}", ball, obstacle),
+            LevelError::BallStartInHole { ball, hole } =>
+                write!(f, "ball {} start is over hole {}", ball, hole),
+            LevelError::BallStartsOverlap { ball, other } =>
+                write!(f, "ball {} start overlaps ball {}", ball, other),
+            LevelError::BallsToWin { required, balls } =>
+                write!(f, "{} balls have to reach the goal but the level has {}", required, balls),
+            LevelError::MissingFinalHole =>
+                write!(f, "level has no final hole"),
+            LevelError::MultipleFinalHoles { count } =>
+                write!(f, "level has {} final holes, expected one", count),
+            LevelError::MotionKeysUnordered { obstacle } =>
+                write!(f, "keyframes of obstacle {} are not in time order", obstacle),
+            LevelError::PolygonTooFewPoints { obstacle } =>
+                write!(f, "polygon of obstacle {} has fewer than 3 distinct points", obstacle),
+            LevelError::PolygonNotConvex { obstacle } =>
+                write!(f, "polygon of obstacle {} is not convex", obstacle),
+            LevelError::GoalUnreachable { ball } =>
+                write!(f, "final hole can not be reached from the start of ball {}", ball),
+        }
+    }
+}
+
+fn to_vec(position: (f32, f32)) -> Vec2 {
+    Vec2::new(position.0, position.1)
+}
+
+// Distance from `point` to the obstacle footprint, 0.0 when inside
+fn obstacle_distance(obstacle: &ObstacleDef, point: Vec2) -> f32 {
+    let diff = point - to_vec(obstacle.position);
+    let local = obstacle.rotation_quat().inverse() * Vec3::new(diff.x, 0.0, diff.y);
+    colider_distance(&obstacle.colider(), Vec2::new(local.x, local.z))
+}
+
+// Corners of a polygon colider without repeated points, closing the
+// outline by repeating the first point is allowed
+fn polygon_corners(points: &[(f32, f32)]) -> Vec<Vec2> {
+    let mut corners: Vec<Vec2> = Vec::new();
+    for point in points.iter().copied().map(to_vec) {
+        if corners.last().map_or(true, |last| last.distance(point) > f32::EPSILON) {
+            corners.push(point);
+        }
+    }
+    while corners.len() > 1 && corners[0].distance(corners[corners.len() - 1]) <= f32::EPSILON {
+        corners.pop();
+    }
+    corners
+}
+
+// The collision sweep only handles convex outlines: every corner turns the
+// same way and the outline goes around once, so it has an area and does not
+// cross itself
+fn is_convex(corners: &[Vec2]) -> bool {
+    let n = corners.len();
+    let edge = |i: usize| corners[(i + 1) % n] - corners[i % n];
+    let area: f32 = (0..n).map(|i| corners[i].perp_dot(corners[(i + 1) % n])).sum();
+    if area.abs() < f32::EPSILON {
+        return false;
+    }
+
+    let mut winding = 0.0;
+    for i in 0..n {
+        let turn = edge(i).perp_dot(edge(i + 1));
+        if turn * area.signum() < -1e-6 {
+            return false;
+        }
+        winding += edge(i).angle_between(edge(i + 1));
+    }
+    (winding.abs() - std::f32::consts::TAU).abs() < 1e-3
+}
+
+fn in_bounds(level: &LevelAsset, point: Vec2, margin: f32) -> bool {
+    point.abs().max_element() <= level.arena_size / 2.0 - margin
+}
+
+// Checks the level for layout mistakes, all problems are reported at once
+pub fn validate_level(level: &LevelAsset) -> Result<(), Vec<LevelError>> {
+    let mut errors = Vec::new();
+    let ball_starts: Vec<Vec2> = level.ball_starts().into_iter().map(to_vec).collect();
+    let capture_distance = BALL_RADIUS + HOLE_RADIUS;
+
+    for (i, hole) in level.holes.iter().enumerate() {
+        let position = to_vec(hole.position);
+        if !in_bounds(level, position, HOLE_RADIUS) {
+            errors.push(LevelError::HoleOutOfBounds { hole: i });
+        }
+        for (j, obstacle) in level.obstacles.iter().enumerate() {
+            if obstacle_distance(obstacle, position) < HOLE_RADIUS {
+                errors.push(LevelError::ObstacleOverlapsHole { obstacle: j, hole: i });
+            }
+        }
+        for (ball, ball_start) in ball_starts.iter().enumerate() {
+            if ball_start.distance(position) < capture_distance {
+                errors.push(LevelError::BallStartInHole { ball, hole: i });
+            }
+        }
+    }
+
+    for (ball, ball_start) in ball_starts.iter().enumerate() {
+        if !in_bounds(level, *ball_start, 0.0) {
+            errors.push(LevelError::BallStartOutOfBounds { ball });
+        }
+        for (i, obstacle) in level.obstacles.iter().enumerate() {
+            if obstacle_distance(obstacle, *ball_start) < BALL_RADIUS {
+                errors.push(LevelError::BallStartInObstacle { ball, obstacle: i });
+            }
+        }
+        for (other, other_start) in ball_starts.iter().enumerate().skip(ball + 1) {
+            if ball_start.distance(*other_start) < 2.0 * BALL_RADIUS {
+                errors.push(LevelError::BallStartsOverlap { ball, other });
+            }
+        }
+    }
+
+    for (i, obstacle) in level.obstacles.iter().enumerate() {
+        if let Some(Colider::PolygonColider(points)) = &obstacle.colider {
+            let corners = polygon_corners(points);
+            if corners.len() < 3 {
+                errors.push(LevelError::PolygonTooFewPoints { obstacle: i });
+            } else if !is_convex(&corners) {
+                errors.push(LevelError::PolygonNotConvex { obstacle: i });
+            }
+        }
+        if let Some(ObstacleMotion::Path { keys, .. }) = &obstacle.motion {
+            if keys.windows(2).any(|pair| pair[1].time < pair[0].time) {
+                errors.push(LevelError::MotionKeysUnordered { obstacle: i });
+            }
+        }
+    }
+
+    let required = level.balls_to_win();
+    if required == 0 || required > ball_starts.len() {
+        errors.push(LevelError::BallsToWin { required, balls: ball_starts.len() });
+    }
+
+    match level.holes.iter().filter(|hole| hole.is_final).count() {
+        0 => errors.push(LevelError::MissingFinalHole),
+        1 => {
+            for (ball, ball_start) in ball_starts.iter().enumerate() {
+                if !goal_reachable(level, *ball_start) {
+                    errors.push(LevelError::GoalUnreachable { ball });
+                }
+            }
+        },
+        count => errors.push(LevelError::MultipleFinalHoles { count }),
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Flood fill over the positions the ball center can occupy without
+// touching a wall, leaving the arena or dropping into a trap.
+// Moving obstacles get out of the way sooner or later and are left out.
+fn goal_reachable(level: &LevelAsset, ball_start: Vec2) -> bool {
+    let capture_distance = BALL_RADIUS + HOLE_RADIUS;
+    let goal = match level.holes.iter().find(|hole| hole.is_final) {
+        Some(hole) => to_vec(hole.position),
+        None => return false,
+    };
+
+    let n = (level.arena_size / VALIDATE_GRID_STEP).ceil() as usize + 1;
+    let origin = -level.arena_size / 2.0;
+    let cell_position = |x: usize, y: usize| Vec2::new(
+        origin + x as f32 * VALIDATE_GRID_STEP,
+        origin + y as f32 * VALIDATE_GRID_STEP,
+    );
+    let cell_of = |point: Vec2| -> Option<(usize, usize)> {
+        let cell = ((point - Vec2::splat(origin)) / VALIDATE_GRID_STEP).round();
+        if cell.x < 0.0 || cell.y < 0.0 || cell.x >= n as f32 || cell.y >= n as f32 {
+            None
+        } else {
+            Some((cell.x as usize, cell.y as usize))
+        }
+    };
+    let is_free = |point: Vec2| {
+        in_bounds(level, point, 0.0) &&
+        level.obstacles.iter()
+            .filter(|obstacle| obstacle.motion.is_none())
+            .all(|obstacle| obstacle_distance(obstacle, point) >= BALL_RADIUS) &&
+        level.holes.iter()
+            .filter(|hole| !hole.is_final)
+            .all(|hole| point.distance(to_vec(hole.position)) >= capture_distance)
+    };
+
+    let start = match cell_of(ball_start) {
+        Some(cell) => cell,
+        None => return false,
+    };
+
+    let mut visited = vec![false; n * n];
+    let mut queue = VecDeque::new();
+    visited[start.0 * n + start.1] = true;
+    queue.push_back(start);
+
+    while let Some((x, y)) = queue.pop_front() {
+        if cell_position(x, y).distance(goal) < capture_distance {
+            return true;
+        }
+
+        let neighbours = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for (nx, ny) in neighbours {
+            if nx >= n || ny >= n || visited[nx * n + ny] {
+                continue;
+            }
+            visited[nx * n + ny] = true;
+            if is_free(cell_position(nx, ny)) {
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    false
+}
+
+pub fn format_errors(errors: &[LevelError]) -> String {
+    errors.iter()
+        .map(|error| error.to_string())
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+// Why the asset loader rejects a level file, None if it would load
+pub fn level_file_problem(path: &Path) -> Option<String> {
+    match load_ron::<LevelAsset>(path) {
+        Ok(level) => validate_level(&level).err().map(|errors| format_errors(&errors)),
+        Err(err) => Some(format!("can not be read: {}", err)),
+    }
+}
+
+// `dipl validate <level files>`, returns the process exit code
+pub fn validate_command(paths: &[String]) -> i32 {
+    if paths.is_empty() {
+        eprintln!("usage: dipl validate <level.ron>...");
+        return 2;
+    }
+
+    let mut exit_code = 0;
+    for path in paths {
+        let level: LevelAsset = match load_ron(Path::new(path)) {
+            Ok(level) => level,
+            Err(err) => {
+                println!("{}: can not be read: {}", path, err);
+                exit_code = 1;
+                continue;
+            },
+        };
+
+        match validate_level(&level) {
+            Ok(()) => println!("{}: ok", path),
+            Err(errors) => {
+                println!("{}: {} problem(s)", path, errors.len());
+                for error in errors {
+                    println!("  - {}", error);
+                }
+                exit_code = 1;
+            },
+        }
+    }
+
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obstacle::Keyframe;
+
+    // One ball on the left, the goal on the right of an empty arena
+    fn level() -> LevelAsset {
+        LevelAsset {
+            arena_size: 12.0,
+            ball_start: (-4.0, 0.0),
+            extra_balls: Vec::new(),
+            balls_to_win: None,
+            holes: vec![HoleDef { position: (4.0, 0.0), is_final: true }],
+            obstacles: Vec::new(),
+            floor: default(),
+            zones: Vec::new(),
+        }
+    }
+
+    fn wall(position: (f32, f32), size: (f32, f32)) -> ObstacleDef {
+        ObstacleDef { position, size, rotation: 0.0, colider: None, material: default(), motion: None }
+    }
+
+    fn errors(level: &LevelAsset) -> Vec<LevelError> {
+        validate_level(level).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn accepts_a_valid_level() {
+        assert_eq!(validate_level(&level()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_hole_outside_of_the_arena() {
+        let mut level = level();
+        level.holes.push(HoleDef { position: (7.0, 0.0), is_final: false });
+        assert_eq!(errors(&level), vec![LevelError::HoleOutOfBounds { hole: 1 }]);
+    }
+
+    #[test]
+    fn rejects_an_obstacle_over_a_hole() {
+        let mut level = level();
+        level.obstacles.push(wall((4.0, 0.0), (1.0, 1.0)));
+        let errors = errors(&level);
+        assert!(errors.contains(&LevelError::ObstacleOverlapsHole { obstacle: 0, hole: 0 }), "{:?}", errors);
+    }
+
+    #[test]
+    fn rejects_ball_starts_in_obstacles_holes_and_each_other() {
+        let mut level = level();
+        level.ball_start = (-4.0, 0.0);
+        level.extra_balls = vec![(-4.5, 0.0), (0.0, 0.0), (-2.0, 4.0)];
+        level.obstacles.push(wall((0.0, 0.0), (1.0, 1.0)));
+        level.holes.push(HoleDef { position: (-2.0, 4.2), is_final: false });
+        let errors = errors(&level);
+        assert!(errors.contains(&LevelError::BallStartsOverlap { ball: 0, other: 1 }), "{:?}", errors);
+        assert!(errors.contains(&LevelError::BallStartInObstacle { ball: 2, obstacle: 0 }), "{:?}", errors);
+        assert!(errors.contains(&LevelError::BallStartInHole { ball: 3, hole: 1 }), "{:?}", errors);
+    }
+
+    #[test]
+    fn rejects_a_ball_start_outside_of_the_arena() {
+        let mut level = level();
+        level.ball_start = (-6.5, 0.0);
+        let errors = errors(&level);
+        assert!(errors.contains(&LevelError::BallStartOutOfBounds { ball: 0 }), "{:?}", errors);
+    }
+
+    #[test]
+    fn rejects_more_balls_to_win_than_balls() {
+        let mut level = level();
+        level.balls_to_win = Some(2);
+        assert_eq!(errors(&level), vec![LevelError::BallsToWin { required: 2, balls: 1 }]);
+
+        level.balls_to_win = Some(0);
+        assert_eq!(errors(&level), vec![LevelError::BallsToWin { required: 0, balls: 1 }]);
+    }
+
+    #[test]
+    fn rejects_a_missing_or_second_final_hole() {
+        let mut level = level();
+        level.holes[0].is_final = false;
+        assert_eq!(errors(&level), vec![LevelError::MissingFinalHole]);
+
+        level.holes[0].is_final = true;
+        level.holes.push(HoleDef { position: (0.0, 4.0), is_final: true });
+        assert_eq!(errors(&level), vec![LevelError::MultipleFinalHoles { count: 2 }]);
+    }
+
+    #[test]
+    fn rejects_unordered_motion_keys() {
+        let mut level = level();
+        let mut gate = wall((0.0, 4.0), (1.0, 0.4));
+        gate.motion = Some(ObstacleMotion::Path {
+            keys: vec![
+                Keyframe { time: 1.0, offset: (0.0, 0.0), rotation: 0.0 },
+                Keyframe { time: 0.5, offset: (1.0, 0.0), rotation: 0.0 },
+            ],
+            ping_pong: false,
+        });
+        level.obstacles.push(gate);
+        assert_eq!(errors(&level), vec![LevelError::MotionKeysUnordered { obstacle: 0 }]);
+    }
+
+    fn polygon_errors(points: &[(f32, f32)]) -> Vec<LevelError> {
+        let mut level = level();
+        let mut obstacle = wall((0.0, 4.0), (0.0, 0.0));
+        obstacle.colider = Some(Colider::PolygonColider(points.to_vec()));
+        level.obstacles.push(obstacle);
+        errors(&level)
+    }
+
+    #[test]
+    fn accepts_convex_polygons_in_either_winding() {
+        assert_eq!(polygon_errors(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]), vec![]);
+        assert_eq!(polygon_errors(&[(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)]), vec![]);
+        // Closed by repeating the first point, with a corner in the middle of an edge
+        assert_eq!(polygon_errors(&[(0.0, 0.0), (0.5, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0)]), vec![]);
+    }
+
+    #[test]
+    fn rejects_polygons_with_too_few_points() {
+        let too_few = vec![LevelError::PolygonTooFewPoints { obstacle: 0 }];
+        assert_eq!(polygon_errors(&[]), too_few);
+        assert_eq!(polygon_errors(&[(0.0, 0.0), (1.0, 0.0)]), too_few);
+        assert_eq!(polygon_errors(&[(0.0, 0.0), (1.0, 0.0), (1.0, 0.0), (0.0, 0.0)]), too_few);
+    }
+
+    #[test]
+    fn rejects_concave_polygons() {
+        let not_convex = vec![LevelError::PolygonNotConvex { obstacle: 0 }];
+        // L shape
+        assert_eq!(polygon_errors(&[(0.0, 0.0), (1.0, 0.0), (1.0, 0.4), (0.4, 0.4), (0.4, 1.0), (0.0, 1.0)]), not_convex);
+        // Star, every corner turns the same way but the outline goes around twice
+        let star: Vec<(f32, f32)> = (0..5)
+            .map(|i| {
+                let angle = (i * 2) as f32 * std::f32::consts::TAU / 5.0;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        assert_eq!(polygon_errors(&star), not_convex);
+        // All on one line
+        assert_eq!(polygon_errors(&[(0.0, 0.0), (0.5, 0.0), (1.0, 0.0)]), not_convex);
+    }
+
+    #[test]
+    fn rejects_a_goal_walled_off() {
+        let mut level = level();
+        level.obstacles.push(wall((0.0, 0.0), (0.4, 12.0)));
+        assert_eq!(errors(&level), vec![LevelError::GoalUnreachable { ball: 0 }]);
+    }
+
+    #[test]
+    fn rejects_a_goal_behind_traps() {
+        let mut level = level();
+        for i in -11..=11 {
+            level.holes.push(HoleDef { position: (0.0, i as f32 * 0.5), is_final: false });
+        }
+        assert_eq!(errors(&level), vec![LevelError::GoalUnreachable { ball: 0 }]);
+    }
+
+    #[test]
+    fn moving_walls_do_not_block_the_goal() {
+        let mut level = level();
+        let mut gate = wall((0.0, 0.0), (0.4, 12.0));
+        gate.motion = Some(ObstacleMotion::Rotate { pivot: (0.0, 0.0), speed: 45.0 });
+        level.obstacles.push(gate);
+        assert_eq!(validate_level(&level), Ok(()));
+    }
+}